@@ -0,0 +1,97 @@
+//! Compares the layout dispatch of `MiMalloc` against always calling the
+//! `_aligned` mimalloc API, for `Box<u64>`-style workloads.
+//!
+//! Run with `cargo bench --bench small_alloc`.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ffi::c_void;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use libmimalloc_sys::{mi_free, mi_malloc_aligned, mi_zalloc_aligned};
+use mimalloc::MiMalloc;
+
+#[global_allocator]
+static GLOBAL: MiMalloc = MiMalloc;
+
+fn box_u64(c: &mut Criterion) {
+    let mut group = c.benchmark_group("box_u64");
+    let layout = Layout::new::<u64>();
+    group.bench_function("mi_malloc_aligned", |b| {
+        b.iter(|| unsafe {
+            let ptr = mi_malloc_aligned(layout.size(), layout.align());
+            mi_free(black_box(ptr));
+        })
+    });
+    group.bench_function("MiMalloc::alloc", |b| {
+        b.iter(|| unsafe {
+            let ptr = MiMalloc.alloc(layout);
+            MiMalloc.dealloc(black_box(ptr), layout);
+        })
+    });
+    group.bench_function("Box::new", |b| b.iter(|| Box::new(black_box(1u64))));
+    group.finish();
+}
+
+fn zeroed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("zeroed_64");
+    let layout = Layout::new::<[u64; 8]>();
+    group.bench_function("mi_zalloc_aligned", |b| {
+        b.iter(|| unsafe {
+            let ptr = mi_zalloc_aligned(layout.size(), layout.align());
+            mi_free(black_box(ptr));
+        })
+    });
+    group.bench_function("MiMalloc::alloc_zeroed", |b| {
+        b.iter(|| unsafe {
+            let ptr = MiMalloc.alloc_zeroed(layout);
+            MiMalloc.dealloc(black_box(ptr), layout);
+        })
+    });
+    group.finish();
+}
+
+fn many_boxes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("many_boxes_1000");
+    let layout = Layout::new::<u64>();
+    let mut ptrs = vec![core::ptr::null_mut::<c_void>(); 1000];
+    group.bench_function("mi_malloc_aligned", |b| {
+        b.iter(|| unsafe {
+            for ptr in ptrs.iter_mut() {
+                *ptr = mi_malloc_aligned(layout.size(), layout.align());
+            }
+            for ptr in ptrs.iter() {
+                mi_free(black_box(*ptr));
+            }
+        })
+    });
+    // Reuse the vector, so that only the boxes are allocated.
+    let mut boxes: Vec<Box<u64>> = Vec::with_capacity(1000);
+    group.bench_function("Box::new", |b| {
+        b.iter(|| {
+            boxes.extend((0..1000).map(|i| Box::new(black_box(i))));
+            black_box(&boxes);
+            boxes.clear();
+        })
+    });
+    group.finish();
+}
+
+fn over_aligned(c: &mut Criterion) {
+    let mut group = c.benchmark_group("over_aligned_64");
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    group.bench_function("mi_malloc_aligned", |b| {
+        b.iter(|| unsafe {
+            let ptr = mi_malloc_aligned(layout.size(), layout.align());
+            mi_free(black_box(ptr));
+        })
+    });
+    group.bench_function("MiMalloc::alloc", |b| {
+        b.iter(|| unsafe {
+            let ptr = MiMalloc.alloc(layout);
+            MiMalloc.dealloc(black_box(ptr), layout);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, box_u64, zeroed, many_boxes, over_aligned);
+criterion_main!(benches);
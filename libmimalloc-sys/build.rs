@@ -1,16 +1,40 @@
 use std::env;
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 fn main() {
-    let mut build = cc::Build::new();
-
     let version = if env::var("CARGO_FEATURE_V2").is_ok() {
         "v2"
     } else {
         "v3"
     };
 
+    println!("cargo:rerun-if-env-changed=MIMALLOC_SYS_DEFINES");
+    let defines = match env::var("MIMALLOC_SYS_DEFINES") {
+        Ok(value) => parse_defines(&value),
+        Err(_) => Vec::new(),
+    };
+
+    println!(
+        "cargo:rustc-check-cfg=cfg(mimalloc_secure_level, values(\"0\", \"1\", \"2\", \"3\", \"4\", \"unknown\"))"
+    );
+    println!("cargo:rustc-check-cfg=cfg(mimalloc_guarded, values(\"0\", \"1\", \"unknown\"))");
+
+    println!("cargo:rerun-if-env-changed=MIMALLOC_SYS_USE_SYSTEM");
+    let use_system = env::var_os("CARGO_FEATURE_SYSTEM").is_some()
+        || env::var("MIMALLOC_SYS_USE_SYSTEM").as_deref() == Ok("1");
+    if use_system && link_system(version) {
+        if !defines.is_empty() {
+            println!("cargo:warning=MIMALLOC_SYS_DEFINES has no effect on the system mimalloc");
+        }
+        println!("cargo:rustc-cfg=mimalloc_secure_level=\"unknown\"");
+        println!("cargo:rustc-cfg=mimalloc_guarded=\"unknown\"");
+        return;
+    }
+
+    let mut build = cc::Build::new();
+
     let cargo_manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
     let include_root = Path::new(&cargo_manifest_dir)
         .join("c_src")
@@ -78,8 +102,69 @@ fn main() {
         }
     }
 
-    if env::var_os("CARGO_FEATURE_SECURE").is_some() {
-        build.define("MI_SECURE", "4");
+    // `secure` enables `secure_4`, and the highest enabled level wins.
+    let secure_level = (1..=4)
+        .rev()
+        .find(|level| env::var_os(format!("CARGO_FEATURE_SECURE_{level}")).is_some());
+    let defined_level = defines.iter().find(|(name, _)| name == "MI_SECURE");
+    let secure_level = match (secure_level, defined_level) {
+        (Some(_), Some(_)) => {
+            panic!(
+                "MIMALLOC_SYS_DEFINES sets MI_SECURE, which conflicts with the `secure` features"
+            )
+        }
+        (Some(level), None) => {
+            build.define("MI_SECURE", level.to_string().as_str());
+            level.to_string()
+        }
+        (None, Some((_, level))) => level.clone(),
+        (None, None) => "0".to_string(),
+    };
+    // Make the level available as `MI_SECURE_LEVEL`.
+    println!("cargo:rustc-cfg=mimalloc_secure_level=\"{secure_level}\"");
+
+    let track_valgrind = env::var_os("CARGO_FEATURE_TRACK_VALGRIND").is_some();
+    let track_asan = env::var_os("CARGO_FEATURE_TRACK_ASAN").is_some();
+    let guarded = env::var_os("CARGO_FEATURE_GUARDED").is_some();
+    if track_valgrind && track_asan {
+        panic!("the `track_valgrind` and `track_asan` features cannot be combined");
+    }
+    for (enabled, name, feature) in [
+        (track_valgrind, "MI_TRACK_VALGRIND", "track_valgrind"),
+        (track_asan, "MI_TRACK_ASAN", "track_asan"),
+        (guarded, "MI_GUARDED", "guarded"),
+    ] {
+        if enabled && defines.iter().any(|(defined, _)| defined == name) {
+            panic!(
+                "MIMALLOC_SYS_DEFINES sets {}, which conflicts with the `{}` feature",
+                name, feature
+            );
+        }
+    }
+    if track_valgrind {
+        // Annotate blocks for memcheck, which requires the valgrind headers.
+        build.define("MI_TRACK_VALGRIND", "1");
+    }
+    if track_asan {
+        // The annotations are no-ops unless mimalloc itself is built with
+        // ASan, and the crate graph must be built with `-Zsanitizer=address`
+        // to link the runtime.
+        build.define("MI_TRACK_ASAN", "1");
+        build.flag("-fsanitize=address");
+        build.flag_if_supported("-fno-omit-frame-pointer");
+    }
+    if guarded {
+        // Guards 1 in 4000 allocations by default (none with `MI_DEBUG`),
+        // tuned at runtime with the `guarded_*` options.
+        build.define("MI_GUARDED", "1");
+    }
+    let guarded_defined = defines
+        .iter()
+        .any(|(name, value)| name == "MI_GUARDED" && value != "0");
+    if (guarded || guarded_defined) && !defines.iter().any(|(name, _)| name == "MI_PADDING") {
+        // Without padding, mimalloc counts the guard page in the usable size
+        // of a guarded object, so `mi_realloc` copies from the guard page.
+        build.define("MI_PADDING", "1");
     }
 
     if target_os == "windows" && env::var_os("CARGO_FEATURE_WIN_DIRECT_TLS").is_some() {
@@ -113,6 +198,27 @@ fn main() {
             build.define("NDEBUG", None);
         }
     }
+    // mimalloc turns on guard pages in debug builds unless `MI_GUARDED` is
+    // defined. Make the result available as `MI_GUARDED`.
+    let guarded_build = match defines.iter().find(|(name, _)| name == "MI_GUARDED") {
+        Some((_, value)) => value != "0",
+        None => guarded || debug_enabled,
+    };
+    println!(
+        "cargo:rustc-cfg=mimalloc_guarded=\"{}\"",
+        if guarded_build { 1 } else { 0 }
+    );
+
+    for (name, value) in &defines {
+        build.define(name, value.as_str());
+    }
+    // Make the extra defines available to consumers via the
+    // `DEP_MIMALLOC_DEFINES` environment variable.
+    let defines: Vec<String> = defines
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+    println!("cargo:DEFINES={}", defines.join(","));
 
     build.compile("mimalloc");
 
@@ -135,3 +241,203 @@ fn main() {
         }
     }
 }
+
+/// The mimalloc compile options that may be set through
+/// `MIMALLOC_SYS_DEFINES`, with the largest value each accepts.
+const EXTRA_DEFINES: &[(&str, u32)] = &[
+    ("MI_SECURE", 4),
+    ("MI_PADDING", 1),
+    ("MI_TRACK_VALGRIND", 1),
+    ("MI_TRACK_ASAN", 1),
+    ("MI_STAT", 2),
+    ("MI_GUARDED", 1),
+    ("MI_SKIP_COLLECT_ON_EXIT", 1),
+];
+
+/// Parse the `NAME=VALUE` pairs of `MIMALLOC_SYS_DEFINES`, separated by
+/// commas or whitespace, where a bare `NAME` means `NAME=1`.
+///
+/// Panics on unknown options and out of range values, rather than passing
+/// on something mimalloc silently ignores.
+fn parse_defines(value: &str) -> Vec<(String, String)> {
+    let mut defines: Vec<(String, String)> = Vec::new();
+    for define in value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|define| !define.is_empty())
+    {
+        let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+        let max = match EXTRA_DEFINES.iter().find(|(known, _)| *known == name) {
+            Some((_, max)) => *max,
+            None => {
+                let known: Vec<&str> = EXTRA_DEFINES.iter().map(|(name, _)| *name).collect();
+                panic!(
+                    "MIMALLOC_SYS_DEFINES: unknown option `{}`, expected one of {}",
+                    name,
+                    known.join(", ")
+                );
+            }
+        };
+        match value.parse::<u32>() {
+            Ok(level) if level <= max => {}
+            _ => panic!(
+                "MIMALLOC_SYS_DEFINES: `{}` must be an integer from 0 to {}, got `{}`",
+                name, max, value
+            ),
+        }
+        if defines.iter().any(|(defined, _)| defined == name) {
+            panic!("MIMALLOC_SYS_DEFINES: `{}` is set more than once", name);
+        }
+        defines.push((name.to_string(), value.to_string()));
+    }
+    defines
+}
+
+/// Link an installed libmimalloc found with pkg-config, if its version matches
+/// the ABI of the bindings for `version`.
+///
+/// Whether it is linked statically follows the pkg-config conventions, e.g.
+/// `MIMALLOC_STATIC=1` or `MIMALLOC_DYNAMIC=1`. Returns false, so that the
+/// vendored sources are built instead, if no matching library is found.
+fn link_system(version: &str) -> bool {
+    // The version in `mimalloc.pc` is the one `mi_version()` reports. The
+    // bindings follow the vendored sources (`MI_MALLOC_VERSION` 20302 and
+    // 30302), so older minor versions may lack some of their symbols.
+    let range = if version == "v2" {
+        "2.3.0".."3.0.0"
+    } else {
+        "3.3.0".."4.0.0"
+    };
+    let probe = |cargo_metadata| {
+        pkg_config::Config::new()
+            .range_version(range.clone())
+            .cargo_metadata(cargo_metadata)
+            .probe("mimalloc")
+            .map_err(|err| err.to_string())
+    };
+    // Only emit the link flags once the library itself passed the check.
+    let library = match probe(false)
+        .and_then(|library| check_system_version(&library, &range))
+        .and_then(|()| probe(true))
+    {
+        Ok(library) => library,
+        Err(err) => {
+            for line in err.lines().filter(|line| !line.trim().is_empty()) {
+                println!("cargo:warning={}", line);
+            }
+            println!(
+                "cargo:warning=no system mimalloc in [{}, {}) found, building the vendored {}",
+                range.start, range.end, version
+            );
+            return false;
+        }
+    };
+
+    // `secure` enables `secure_4`, which reports it.
+    for feature in [
+        "SECURE_1",
+        "SECURE_2",
+        "SECURE_3",
+        "SECURE_4",
+        "DEBUG",
+        "DEBUG_IN_DEBUG",
+        "OVERRIDE",
+        "LOCAL_DYNAMIC_TLS",
+        "WIN_DIRECT_TLS",
+        "NO_THP",
+        "TRACK_VALGRIND",
+        "TRACK_ASAN",
+        "GUARDED",
+    ] {
+        if env::var_os(format!("CARGO_FEATURE_{feature}")).is_some() {
+            println!(
+                "cargo:warning=the `{}` feature has no effect on the system mimalloc {}",
+                feature.to_lowercase(),
+                library.version
+            );
+        }
+    }
+
+    if let Some(include_dir) = library.include_paths.first() {
+        println!("cargo:INCLUDE_DIR={}", include_dir.display());
+    }
+    true
+}
+
+/// Build and run a program that calls `mi_version()` of the system library,
+/// and check that its major and minor version is in `range`.
+///
+/// A `.pc` file can describe another library than the one found at link
+/// time. The check needs to run the program, so when cross compiling, or with
+/// an MSVC-like compiler, only the pkg-config version is checked.
+fn check_system_version(library: &pkg_config::Library, range: &Range<&str>) -> Result<(), String> {
+    let compiler = cc::Build::new()
+        .cargo_metadata(false)
+        .try_get_compiler()
+        .map_err(|err| err.to_string())?;
+    if env::var("HOST") != env::var("TARGET") || compiler.is_like_msvc() {
+        println!(
+            "cargo:warning=cannot run mi_version() of the system mimalloc {} for this target, \
+             relying on its pkg-config version",
+            library.version
+        );
+        return Ok(());
+    }
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let source = out_dir.join("mi_version.c");
+    let program = out_dir.join("mi_version");
+    fs::write(
+        &source,
+        "#include <stdio.h>\n\
+         int mi_version(void);\n\
+         int main(void) { printf(\"%d\\n\", mi_version()); return 0; }\n",
+    )
+    .map_err(|err| err.to_string())?;
+
+    let mut build = compiler.to_command();
+    build.arg(&source).arg("-o").arg(&program);
+    for path in &library.link_paths {
+        build.arg("-L").arg(path);
+    }
+    for lib in &library.libs {
+        build.arg(format!("-l{}", lib));
+    }
+    let output = build.output().map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "failed to link against the system mimalloc: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let mut run = std::process::Command::new(&program);
+    // Find the shared library where pkg-config says it is.
+    for var in ["LD_LIBRARY_PATH", "DYLD_LIBRARY_PATH"] {
+        let mut paths = library.link_paths.clone();
+        if let Some(existing) = env::var_os(var) {
+            paths.extend(env::split_paths(&existing));
+        }
+        run.env(var, env::join_paths(paths).map_err(|err| err.to_string())?);
+    }
+    let output = run.output().map_err(|err| err.to_string())?;
+    let reported = String::from_utf8_lossy(&output.stdout);
+    let reported: u32 = match reported.trim().parse() {
+        Ok(reported) if output.status.success() => reported,
+        _ => return Err("failed to run mi_version() of the system mimalloc".to_string()),
+    };
+    // `mi_version()` is major * 10000 + minor * 100 + patch.
+    let minor_range = minor_version(range.start)..minor_version(range.end);
+    if !minor_range.contains(&(reported / 100)) {
+        return Err(format!(
+            "the system mimalloc {} reports mi_version() {}, expected [{}, {})",
+            library.version, reported, range.start, range.end
+        ));
+    }
+    Ok(())
+}
+
+/// The `major * 100 + minor` of a `major.minor.patch` version.
+fn minor_version(version: &str) -> u32 {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().unwrap());
+    parts.next().unwrap() * 100 + parts.next().unwrap()
+}
@@ -4,10 +4,6 @@ use core::ffi::c_void;
 
 use cty::{c_char, c_int, c_long, c_ulonglong};
 
-/// The maximum number of bytes which may be used as an argument to a function
-/// in the `_small` family ([`mi_malloc_small`], [`mi_zalloc_small`], etc).
-pub const MI_SMALL_SIZE_MAX: usize = 128 * core::mem::size_of::<*mut c_void>();
-
 extern "C" {
     /// Allocate `count` items of `size` length each.
     ///
@@ -32,21 +28,6 @@ extern "C" {
     /// same as [`mi_realloc(p, count * size)`](crate::mi_realloc).
     pub fn mi_reallocn(p: *mut c_void, count: usize, size: usize) -> *mut c_void;
 
-    /// Try to re-allocate memory to `newsize` bytes _in place_.
-    ///
-    /// Returns null on out-of-memory or if the memory could not be expanded in
-    /// place. On success, returns the same pointer as `p`.
-    ///
-    /// If `newsize` is larger than the original `size` allocated for `p`, the
-    /// bytes after `size` are uninitialized.
-    ///
-    /// If null is returned, the original pointer is not freed.
-    ///
-    /// Note: Conceptually, this is a realloc-like which returns null if it
-    /// would be forced to reallocate memory and copy. In practice it's
-    /// equivalent testing against [`mi_usable_size`](crate::mi_usable_size).
-    pub fn mi_expand(p: *mut c_void, newsize: usize) -> *mut c_void;
-
     /// Re-allocate memory to `newsize` bytes.
     ///
     /// This differs from [`mi_realloc`](crate::mi_realloc) in that on failure,
@@ -183,34 +164,12 @@ extern "C" {
         offset: usize,
     ) -> *mut c_void;
 
-    /// Allocate an object of no more than [`MI_SMALL_SIZE_MAX`] bytes.
-    ///
-    /// Does not check that `size` is indeed small.
-    ///
-    /// Note: Currently [`mi_malloc`](crate::mi_malloc) checks if `size` is
-    /// small and calls this if
-    /// so at runtime, so its' only worth using if you know for certain.
-    pub fn mi_malloc_small(size: usize) -> *mut c_void;
-
-    /// Allocate an zero-initialized object of no more than
-    /// [`MI_SMALL_SIZE_MAX`] bytes.
-    ///
-    /// Does not check that `size` is indeed small.
-    ///
-    /// Note: Currently [`mi_zalloc`](crate::mi_zalloc) checks if `size` is
-    /// small and calls this if so at runtime, so its' only worth using if you
-    /// know for certain.
-    pub fn mi_zalloc_small(size: usize) -> *mut c_void;
-
-    /// Free a small object. Only use to free objects from [`mi_malloc_small`]
-    /// or [`mi_zalloc_small`]. Potentially a tiny bit faster than [`mi_free`](crate::mi_free).
+    /// Free a small object. Only use to free objects from
+    /// [`mi_malloc_small`](crate::mi_malloc_small) or
+    /// [`mi_zalloc_small`](crate::mi_zalloc_small). Potentially a tiny bit
+    /// faster than [`mi_free`](crate::mi_free).
     pub fn mi_free_small(p: *mut c_void);
 
-    /// Return the available bytes in a memory block.
-    ///
-    /// The returned size can be used to call `mi_expand` successfully.
-    pub fn mi_usable_size(p: *const c_void) -> usize;
-
     /// Return the used allocation size.
     ///
     /// Returns the size `n` that will be allocated, where `n >= size`.
@@ -257,8 +216,8 @@ extern "C" {
     /// verifies that `p` is actually aligned to `alignment` and is usable for
     /// at least `size` bytes, before delegating to `mi_free`.
     ///
-    /// However, currently there's no way to have this crate enable mimalloc's
-    /// debug assertions, so these checks aren't particularly useful.
+    /// These assertions are only checked when mimalloc is built with the
+    /// `debug` (or `debug_in_debug`) feature.
     ///
     /// Note: It's legal to pass null to this function, and you are not required
     /// to use this to deallocate memory from an aligned allocation function.
@@ -273,8 +232,8 @@ extern "C" {
     /// verifies that `p` is actually aligned to `alignment` and is usable for
     /// at least `size` bytes, before delegating to `mi_free`.
     ///
-    /// However, currently there's no way to have this crate enable mimalloc's
-    /// debug assertions, so these checks aren't particularly useful.
+    /// These assertions are only checked when mimalloc is built with the
+    /// `debug` (or `debug_in_debug`) feature.
     ///
     /// Note: It's legal to pass null to this function.
     pub fn mi_free_size(p: *mut c_void, size: usize);
@@ -288,8 +247,8 @@ extern "C" {
     /// verifies that `p` is actually aligned to `alignment` and is usable for
     /// at least `size` bytes, before delegating to `mi_free`.
     ///
-    /// However, currently there's no way to have this crate enable mimalloc's
-    /// debug assertions, so these checks aren't particularly useful.
+    /// These assertions are only checked when mimalloc is built with the
+    /// `debug` (or `debug_in_debug`) feature.
     ///
     /// Note: It's legal to pass null to this function.
     pub fn mi_free_aligned(p: *mut c_void, alignment: usize);
@@ -323,7 +282,8 @@ extern "C" {
 
     /// Return the mimalloc version number.
     ///
-    /// For example version 1.6.3 would return the number `163`.
+    /// The major version is followed by two digits each for the minor and
+    /// patch versions, for example version 2.1.7 returns the number `20107`.
     pub fn mi_version() -> c_int;
 
     /// Initialize mimalloc on a thread.
@@ -502,6 +462,17 @@ pub const mi_option_show_stats: mi_option_t = 1;
 /// Print verbose messages to `stderr`.
 pub const mi_option_verbose: mi_option_t = 2;
 
+#[cfg(feature = "v2")]
+/// Option (experimental) Eagerly commit segments (after `eager_commit_delay` segments) (=1).
+pub const mi_option_eager_commit: mi_option_t = 3;
+
+/// Option (experimental) Eagerly commit arenas. Use 2 to enable just on overcommit systems (=2).
+pub const mi_option_arena_eager_commit: mi_option_t = 4;
+
+/// Option (experimental) Should a memory purge decommit? (=1). Set to 0 to use memory reset on a
+/// purge (instead of decommit).
+pub const mi_option_purge_decommits: mi_option_t = 5;
+
 /// ### The following options are experimental
 ///
 /// Option (experimental) Use large OS pages (2MiB in size) if possible.
@@ -516,6 +487,11 @@ pub const mi_option_verbose: mi_option_t = 2;
 /// instead whenever possible).
 pub const mi_option_large_os_pages: mi_option_t = 6;
 
+/// Option (experimental) Allow use of large (2 or 4 MiB) OS pages, implies eager commit.
+///
+/// This is the current name of [`mi_option_large_os_pages`].
+pub const mi_option_allow_large_os_pages: mi_option_t = 6;
+
 /// Option (experimental) The number of huge OS pages (1GiB in size) to reserve at the start of the program.
 ///
 /// This reserves the huge pages at startup and sometimes this can give a large (latency) performance
@@ -539,16 +515,30 @@ pub const mi_option_reserve_huge_os_pages_at: mi_option_t = 8;
 /// Option (experimental) Reserve specified amount of OS memory at startup, e.g. "1g" or "512m".
 pub const mi_option_reserve_os_memory: mi_option_t = 9;
 
+#[cfg(feature = "v2")]
+/// Option (experimental) Immediately purge delayed purges on thread termination.
+pub const mi_option_abandoned_page_purge: mi_option_t = 12;
+
 #[cfg(feature = "v2")]
 /// Option (experimental) the first N segments per thread are not eagerly committed (=1).
 pub const mi_option_eager_commit_delay: mi_option_t = 14;
 
+/// Option (experimental) Memory purging is delayed by N milli seconds; use 0 for immediate purging
+/// or -1 for no purging at all.
+pub const mi_option_purge_delay: mi_option_t = 15;
+
 /// Option (experimental) Pretend there are at most N NUMA nodes; Use 0 to use the actual detected NUMA nodes at runtime.
 pub const mi_option_use_numa_nodes: mi_option_t = 16;
 
 /// Option (experimental) If set to 1, do not use OS memory for allocation (but only pre-reserved arenas)
 pub const mi_option_limit_os_alloc: mi_option_t = 17;
 
+/// Option (experimental) If set to 1, do not use OS memory for allocation (but only
+/// programmatically reserved arenas).
+///
+/// This is the current name of [`mi_option_limit_os_alloc`].
+pub const mi_option_disallow_os_alloc: mi_option_t = 17;
+
 /// Option (experimental) OS tag to assign to mimalloc'd memory
 pub const mi_option_os_tag: mi_option_t = 18;
 
@@ -562,6 +552,180 @@ pub const mi_option_max_warnings: mi_option_t = 20;
 /// Option (experimental)
 pub const mi_option_max_segment_reclaim: mi_option_t = 21;
 
+/// Option (experimental) If set, release all memory on exit; sometimes used for dynamic unloading
+/// but can be unsafe.
+pub const mi_option_destroy_on_exit: mi_option_t = 22;
+
+/// Option (experimental) Initial memory size for arena reservation (= 1 GiB on 64-bit).
+///
+/// The value is in KiB, use [`mi_option_get_size`] to get it in bytes.
+pub const mi_option_arena_reserve: mi_option_t = 23;
+
+/// Option (experimental) Multiplier for `purge_delay` for the purging delay for arenas.
+pub const mi_option_arena_purge_mult: mi_option_t = 24;
+
+#[cfg(feature = "v2")]
+/// Option (experimental)
+pub const mi_option_purge_extend_delay: mi_option_t = 25;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Allow to reclaim an abandoned segment on a free (=1).
+pub const mi_option_abandoned_reclaim_on_free: mi_option_t = 26;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) If set to 1, do not use arenas for allocation (except if using specific
+/// arena ids).
+pub const mi_option_disallow_arena_alloc: mi_option_t = 27;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Retry on out-of-memory for N milli seconds (=400), set to 0 to disable
+/// retries (only on Windows).
+pub const mi_option_retry_on_oom: mi_option_t = 28;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Allow visiting heap blocks from abandoned threads (=0).
+pub const mi_option_visit_abandoned: mi_option_t = 29;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Minimal rounded object size for guarded objects (=0).
+///
+/// Only used when building with `MI_GUARDED`.
+pub const mi_option_guarded_min: mi_option_t = 30;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Maximal rounded object size for guarded objects (=0).
+///
+/// Only used when building with `MI_GUARDED`.
+pub const mi_option_guarded_max: mi_option_t = 31;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Disregard minimal alignment requirement to always place guarded blocks
+/// exactly in front of a guard page (=0).
+pub const mi_option_guarded_precise: mi_option_t = 32;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) 1 out of N allocations in the min/max range will be guarded (=1000).
+pub const mi_option_guarded_sample_rate: mi_option_t = 33;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Can be set to allow for a (more) deterministic re-execution when a guard
+/// page is triggered (=0).
+pub const mi_option_guarded_sample_seed: mi_option_t = 34;
+
+#[cfg(feature = "v2")]
+/// Option (experimental)
+pub const mi_option_target_segments_per_thread: mi_option_t = 35;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Collect heaps every N (=10000) generic allocation calls.
+pub const mi_option_generic_collect: mi_option_t = 36;
+
+#[cfg(feature = "v2")]
+/// Option (experimental) Allow transparent huge pages? (=1) (on Android =0 by default). Set to 0
+/// to disable THP for the process.
+pub const mi_option_allow_thp: mi_option_t = 37;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) If set to 1, do not use arenas for allocation (except if using specific
+/// arena ids).
+pub const mi_option_disallow_arena_alloc: mi_option_t = 26;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Retry on out-of-memory for N milli seconds (=400), set to 0 to disable
+/// retries (only on Windows).
+pub const mi_option_retry_on_oom: mi_option_t = 27;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Allow visiting heap blocks from abandoned threads (=0).
+pub const mi_option_visit_abandoned: mi_option_t = 28;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Minimal rounded object size for guarded objects (=0).
+///
+/// Only used when building with `MI_GUARDED`.
+pub const mi_option_guarded_min: mi_option_t = 29;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Maximal rounded object size for guarded objects (=0).
+///
+/// Only used when building with `MI_GUARDED`.
+pub const mi_option_guarded_max: mi_option_t = 30;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Disregard minimal alignment requirement to always place guarded blocks
+/// exactly in front of a guard page (=0).
+pub const mi_option_guarded_precise: mi_option_t = 31;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) 1 out of N allocations in the min/max range will be guarded (=1000).
+pub const mi_option_guarded_sample_rate: mi_option_t = 32;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Can be set to allow for a (more) deterministic re-execution when a guard
+/// page is triggered (=0).
+pub const mi_option_guarded_sample_seed: mi_option_t = 33;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Collect heaps every N (=10000) generic allocation calls.
+pub const mi_option_generic_collect: mi_option_t = 34;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Reclaim abandoned pages on a free (=0). -1 disallows always, 0 allows if
+/// the page originated from the current heap, 1 allows always.
+pub const mi_option_page_reclaim_on_free: mi_option_t = 35;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Retain N full (small) pages per size class (=2).
+pub const mi_option_page_full_retain: mi_option_t = 36;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Max candidate pages to consider for allocation (=4).
+pub const mi_option_page_max_candidates: mi_option_t = 37;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Max user space virtual address bits to consider (=48).
+pub const mi_option_max_vabits: mi_option_t = 38;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Commit the full pagemap (to always catch invalid pointer uses) (=0).
+pub const mi_option_pagemap_commit: mi_option_t = 39;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Commit page memory on-demand.
+pub const mi_option_page_commit_on_demand: mi_option_t = 40;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Don't reclaim pages of the same originating heap if we already own N
+/// pages (in that size class) (=-1 (unlimited)).
+pub const mi_option_page_max_reclaim: mi_option_t = 41;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Don't reclaim pages across threads if we already own N pages (in that
+/// size class) (=16).
+pub const mi_option_page_cross_thread_max_reclaim: mi_option_t = 42;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Allow transparent huge pages? (=1) (on Android =0 by default). Set to 0
+/// to disable THP for the process.
+pub const mi_option_allow_thp: mi_option_t = 43;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Minimal purge size (=0, which resolves to either 64 KiB or 2 MiB if THP
+/// is enabled).
+///
+/// The value is in KiB, use [`mi_option_get_size`] to get it in bytes.
+pub const mi_option_minimal_purge_size: mi_option_t = 44;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Maximal object size that can be allocated in an arena (=2GiB on 64-bit).
+///
+/// The value is in KiB, use [`mi_option_get_size`] to get it in bytes.
+pub const mi_option_arena_max_object_size: mi_option_t = 45;
+
+#[cfg(not(feature = "v2"))]
+/// Option (experimental) Associate the local NUMA node with an initial arena allocation.
+pub const mi_option_arena_is_numa_local: mi_option_t = 46;
+
 /// Last option.
 #[cfg(feature = "v2")]
 pub const _mi_option_last: mi_option_t = 38;
@@ -603,6 +767,20 @@ extern "C" {
     /// Note: this function is not thread safe.
     pub fn mi_option_get(option: mi_option_t) -> c_long;
 
+    /// Returns the value of the provided option, clamped to `min..=max`.
+    ///
+    /// Note: this function is not thread safe.
+    pub fn mi_option_get_clamp(option: mi_option_t, min: c_long, max: c_long) -> c_long;
+
+    /// Returns the value of the provided option as a size in bytes.
+    ///
+    /// Options that are stored in KiB (like [`mi_option_reserve_os_memory`]
+    /// and [`mi_option_arena_reserve`]) are converted to bytes, and negative
+    /// values are returned as 0.
+    ///
+    /// Note: this function is not thread safe.
+    pub fn mi_option_get_size(option: mi_option_t) -> usize;
+
     /// Set the option to the given value.
     ///
     /// The value of boolean options is 1 or 0, however experimental options
@@ -674,7 +852,7 @@ pub struct mi_heap_area_t {
     pub reserved: usize,
     /// Current committed bytes of this area.
     pub committed: usize,
-    /// Bytes in use by allocated blocks.
+    /// Number of allocated blocks.
     pub used: usize,
     /// Size in bytes of one block.
     pub block_size: usize,
@@ -767,16 +945,16 @@ extern "C" {
     /// instead of the default.
     pub fn mi_heap_mallocn(heap: *mut mi_heap_t, count: usize, size: usize) -> *mut c_void;
 
-    /// Equivalent to [`mi_malloc_small`], but allocates out of the specific
+    /// Equivalent to [`mi_malloc_small`](crate::mi_malloc_small), but allocates out of the specific
     /// heap instead of the default.
     ///
-    /// `size` must be smaller or equal to [`MI_SMALL_SIZE_MAX`].
+    /// `size` must be smaller or equal to [`MI_SMALL_SIZE_MAX`](crate::MI_SMALL_SIZE_MAX).
     pub fn mi_heap_malloc_small(heap: *mut mi_heap_t, size: usize) -> *mut c_void;
 
-    /// Equivalent to [`mi_zalloc_small`], but allocates out of the specific
+    /// Equivalent to [`mi_zalloc_small`](crate::mi_zalloc_small), but allocates out of the specific
     /// heap instead of the default.
     ///
-    /// `size` must be smaller or equal to [`MI_SMALL_SIZE_MAX`].
+    /// `size` must be smaller or equal to [`MI_SMALL_SIZE_MAX`](crate::MI_SMALL_SIZE_MAX).
     pub fn mi_heap_zalloc_small(heap: *mut mi_heap_t, size: usize) -> *mut c_void;
 
     /// Equivalent to [`mi_realloc`](crate::mi_realloc), but allocates out of
@@ -1014,6 +1192,11 @@ extern "C" {
         arg: *mut c_void,
     ) -> bool;
 
+    #[cfg(feature = "arena")]
+    /// Return the start of the memory area of an arena, and store its size in
+    /// bytes in `size`.
+    pub fn mi_arena_area(arena_id: mi_arena_id_t, size: *mut usize) -> *mut c_void;
+
     #[cfg(feature = "arena")]
     /// Create a heap that only allocates in the specified arena
     pub fn mi_heap_new_in_arena(arena_id: mi_arena_id_t) -> *mut mi_heap_t;
@@ -1053,12 +1236,105 @@ extern "C" {
     /// Return the minimum size for an arena (v3 only).
     pub fn mi_arena_min_size() -> usize;
 
+    #[cfg(not(feature = "v2"))]
+    /// Return the minimum alignment of the start of an arena (v3 only).
+    pub fn mi_arena_min_alignment() -> usize;
+
+    #[cfg(not(feature = "v2"))]
+    /// Return the thread-local heap (`theap`) of `heap` for the calling thread,
+    /// creating it if needed (v3 only).
+    ///
+    /// Returns null if out of memory. The theap may only be used on the
+    /// calling thread, and only while `heap` is alive.
+    pub fn mi_heap_theap(heap: *mut mi_heap_t) -> *mut mi_theap_t;
+
+    #[cfg(not(feature = "v2"))]
+    /// Return the default thread-local heap of the calling thread, which
+    /// backs `mi_malloc` and friends (v3 only).
+    pub fn mi_theap_get_default() -> *mut mi_theap_t;
+
+    // `mi_theap_set_default` is declared in `mimalloc.h`, but mimalloc 3.3.2
+    // does not define it, so it is not bound until a release exports it.
+
+    #[cfg(not(feature = "v2"))]
+    /// Equivalent to [`mi_heap_collect`], but for a thread-local heap in v3.
+    pub fn mi_theap_collect(theap: *mut mi_theap_t, force: bool);
+
+    #[cfg(not(feature = "v2"))]
+    /// Equivalent to [`mi_heap_malloc`], but for a thread-local heap in v3.
+    pub fn mi_theap_malloc(theap: *mut mi_theap_t, size: usize) -> *mut c_void;
+
+    #[cfg(not(feature = "v2"))]
+    /// Equivalent to [`mi_heap_zalloc`], but for a thread-local heap in v3.
+    pub fn mi_theap_zalloc(theap: *mut mi_theap_t, size: usize) -> *mut c_void;
+
+    #[cfg(not(feature = "v2"))]
+    /// Equivalent to [`mi_heap_calloc`], but for a thread-local heap in v3.
+    pub fn mi_theap_calloc(theap: *mut mi_theap_t, count: usize, size: usize) -> *mut c_void;
+
+    #[cfg(not(feature = "v2"))]
+    /// Equivalent to [`mi_heap_malloc_small`], but for a thread-local heap in v3.
+    ///
+    /// `size` must be smaller or equal to [`MI_SMALL_SIZE_MAX`](crate::MI_SMALL_SIZE_MAX).
+    pub fn mi_theap_malloc_small(theap: *mut mi_theap_t, size: usize) -> *mut c_void;
+
     #[cfg(not(feature = "v2"))]
     /// Equivalent to [`mi_heap_zalloc_small`], but for a thread-local heap (`theap`) in v3.
     ///
-    /// `size` must be smaller or equal to [`MI_SMALL_SIZE_MAX`].
+    /// `size` must be smaller or equal to [`MI_SMALL_SIZE_MAX`](crate::MI_SMALL_SIZE_MAX).
     pub fn mi_theap_zalloc_small(theap: *mut mi_theap_t, size: usize) -> *mut c_void;
 
+    #[cfg(not(feature = "v2"))]
+    /// Equivalent to [`mi_heap_malloc_aligned`], but for a thread-local heap in v3.
+    pub fn mi_theap_malloc_aligned(
+        theap: *mut mi_theap_t,
+        size: usize,
+        alignment: usize,
+    ) -> *mut c_void;
+
+    #[cfg(not(feature = "v2"))]
+    /// Equivalent to [`mi_heap_realloc`], but for a thread-local heap in v3.
+    pub fn mi_theap_realloc(theap: *mut mi_theap_t, p: *mut c_void, newsize: usize) -> *mut c_void;
+
+    #[cfg(not(feature = "v2"))]
+    /// Equivalent to [`mi_heap_visit_blocks`], but for a thread-local heap in v3.
+    pub fn mi_theap_visit_blocks(
+        theap: *const mi_theap_t,
+        visit_all_blocks: bool,
+        visitor: mi_block_visit_fun,
+        arg: *mut c_void,
+    ) -> bool;
+
+    #[cfg(not(feature = "v2"))]
+    /// Guard 1 out of `sample_rate` allocations of a thread-local heap with a
+    /// guard page (v3 only, experimental).
+    ///
+    /// A `sample_rate` of 0 disables guarded objects and 1 guards every
+    /// object. A `seed` of 0 uses a random start point. Only effective if
+    /// mimalloc was built with `MI_GUARDED`.
+    pub fn mi_theap_guarded_set_sample_rate(
+        theap: *mut mi_theap_t,
+        sample_rate: usize,
+        seed: usize,
+    );
+
+    #[cfg(not(feature = "v2"))]
+    /// Only guard allocations of a thread-local heap with a size between
+    /// `min` and `max` bytes (v3 only, experimental).
+    pub fn mi_theap_guarded_set_size_bound(theap: *mut mi_theap_t, min: usize, max: usize);
+
+    #[cfg(feature = "v2")]
+    /// Guard 1 out of `sample_rate` allocations of a heap with a guard page
+    /// (v2 only, experimental).
+    ///
+    /// See [`mi_theap_guarded_set_sample_rate`] for v3.
+    pub fn mi_heap_guarded_set_sample_rate(heap: *mut mi_heap_t, sample_rate: usize, seed: usize);
+
+    #[cfg(feature = "v2")]
+    /// Only guard allocations of a heap with a size between `min` and `max`
+    /// bytes (v2 only, experimental).
+    pub fn mi_heap_guarded_set_size_bound(heap: *mut mi_heap_t, min: usize, max: usize);
+
     #[cfg(feature = "arena")]
     /// Manage a particular memory area for use by mimalloc.
     /// This is just like `mi_reserve_os_memory_ex` except that the area should already be
@@ -1070,10 +1346,10 @@ extern "C" {
     ///
     /// - `start` Start of the memory area
     /// - `size` The size of the memory area. Must be large than `MI_ARENA_BLOCK_SIZE` (e.g. 64MB
-    ///          on x86_64 machines).
+    ///   on x86_64 machines).
     /// - `commit` Set true if the memory range is already commited.
     /// - `is_large` Set true if the memory range consists of large files, or if the memory should
-    ///              not be decommitted or protected (like rdma etc.).
+    ///   not be decommitted or protected (like rdma etc.).
     /// - `is_zero` Set true if the memory range consists only of zeros.
     /// - `numa_node` Possible associated numa node or `-1`.
     /// - `exclusive` Only allow allocations if specifically for this arena.
@@ -1094,9 +1370,15 @@ extern "C" {
 
 #[cfg(test)]
 mod tests {
-    use super::super::mi_malloc;
+    use super::super::{mi_malloc, mi_usable_size};
     use super::*;
 
+    #[test]
+    fn it_links_the_bound_major_version() {
+        let major = if cfg!(feature = "v2") { 2 } else { 3 };
+        assert_eq!(unsafe { mi_version() } / 10000, major);
+    }
+
     #[test]
     fn it_calculates_usable_size() {
         let ptr = unsafe { mi_malloc(32) } as *mut u8;
@@ -8,6 +8,45 @@ mod extended;
 #[cfg(feature = "extended")]
 pub use extended::*;
 
+/// The maximum number of bytes which may be used as an argument to a function
+/// in the `_small` family ([`mi_malloc_small`], [`mi_zalloc_small`], etc).
+pub const MI_SMALL_SIZE_MAX: usize = 128 * core::mem::size_of::<*mut c_void>();
+
+/// The `MI_SECURE` level mimalloc was built with, from 0 (no mitigations) to
+/// 4, or `None` when linked against a system mimalloc, whose level is unknown.
+///
+/// It is set by the `secure_1` to `secure_4` features (`secure` is level 4),
+/// or by `MI_SECURE` in `MIMALLOC_SYS_DEFINES`.
+pub const MI_SECURE_LEVEL: Option<u8> = SECURE_LEVEL;
+
+#[cfg(mimalloc_secure_level = "0")]
+const SECURE_LEVEL: Option<u8> = Some(0);
+#[cfg(mimalloc_secure_level = "1")]
+const SECURE_LEVEL: Option<u8> = Some(1);
+#[cfg(mimalloc_secure_level = "2")]
+const SECURE_LEVEL: Option<u8> = Some(2);
+#[cfg(mimalloc_secure_level = "3")]
+const SECURE_LEVEL: Option<u8> = Some(3);
+#[cfg(mimalloc_secure_level = "4")]
+const SECURE_LEVEL: Option<u8> = Some(4);
+#[cfg(mimalloc_secure_level = "unknown")]
+const SECURE_LEVEL: Option<u8> = None;
+
+/// Whether mimalloc was built with `MI_GUARDED`, or `None` when linked against
+/// a system mimalloc.
+///
+/// It is set by the `guarded` feature, by `MI_GUARDED` in
+/// `MIMALLOC_SYS_DEFINES`, and implied by the `debug` feature. Guarded builds
+/// only place guard pages when the `guarded_sample_rate` option is non-zero.
+pub const MI_GUARDED: Option<bool> = GUARDED;
+
+#[cfg(mimalloc_guarded = "0")]
+const GUARDED: Option<bool> = Some(false);
+#[cfg(mimalloc_guarded = "1")]
+const GUARDED: Option<bool> = Some(true);
+#[cfg(mimalloc_guarded = "unknown")]
+const GUARDED: Option<bool> = None;
+
 extern "C" {
     /// Allocate zero-initialized `size` bytes.
     ///
@@ -21,6 +60,25 @@ extern "C" {
     /// Returns a unique pointer if called with `size` 0.
     pub fn mi_malloc(size: usize) -> *mut c_void;
 
+    /// Allocate an object of no more than [`MI_SMALL_SIZE_MAX`] bytes.
+    ///
+    /// Does not check that `size` is indeed small.
+    ///
+    /// Note: Currently [`mi_malloc`] checks if `size` is
+    /// small and calls this if
+    /// so at runtime, so its' only worth using if you know for certain.
+    pub fn mi_malloc_small(size: usize) -> *mut c_void;
+
+    /// Allocate an zero-initialized object of no more than
+    /// [`MI_SMALL_SIZE_MAX`] bytes.
+    ///
+    /// Does not check that `size` is indeed small.
+    ///
+    /// Note: Currently [`mi_zalloc`] checks if `size` is
+    /// small and calls this if so at runtime, so its' only worth using if you
+    /// know for certain.
+    pub fn mi_zalloc_small(size: usize) -> *mut c_void;
+
     /// Re-allocate memory to `newsize` bytes.
     ///
     /// Return pointer to the allocated memory or null if out of memory. If null
@@ -33,6 +91,26 @@ extern "C" {
     /// uninitialized.
     pub fn mi_realloc(p: *mut c_void, newsize: usize) -> *mut c_void;
 
+    /// Try to re-allocate memory to `newsize` bytes _in place_.
+    ///
+    /// Returns null on out-of-memory or if the memory could not be expanded in
+    /// place. On success, returns the same pointer as `p`.
+    ///
+    /// If `newsize` is larger than the original `size` allocated for `p`, the
+    /// bytes after `size` are uninitialized.
+    ///
+    /// If null is returned, the original pointer is not freed.
+    ///
+    /// Note: Conceptually, this is a realloc-like which returns null if it
+    /// would be forced to reallocate memory and copy. In practice it's
+    /// equivalent testing against [`mi_usable_size`].
+    pub fn mi_expand(p: *mut c_void, newsize: usize) -> *mut c_void;
+
+    /// Return the available bytes in a memory block.
+    ///
+    /// The returned size can be used to call [`mi_expand`] successfully.
+    pub fn mi_usable_size(p: *const c_void) -> usize;
+
     /// Allocate `size` bytes aligned by `alignment`, initialized to zero.
     ///
     /// Return pointer to the allocated memory or null if out of memory.
@@ -27,6 +27,8 @@ fn main() {
         .cfg("feature", extended)
         .cfg("feature", (version == "v2").then_some("v2"))
         .fn_cname(|rust, link_name| link_name.unwrap_or(rust).to_string())
+        // set by the build script rather than mimalloc.h.
+        .skip_const(|name| name == "MI_SECURE_LEVEL" || name == "MI_GUARDED")
         // ignore whether or not the option enum is signed.
         .skip_signededness(|c| c.ends_with("_t") || c.ends_with("_e"))
         .type_name(|ty, _is_struct, _is_union| {
@@ -1,4 +1,11 @@
-#![allow(bad_style, unused_imports, unused_macros, clippy::all)]
+#![allow(
+    bad_style,
+    unused_imports,
+    unused_macros,
+    unknown_lints,
+    function_casts_as_integer,
+    clippy::all
+)]
 
 use libmimalloc_sys::*;
 
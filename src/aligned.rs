@@ -0,0 +1,346 @@
+use crate::{MiHeap, MiMalloc};
+use core::ffi::c_void;
+use core::mem;
+use core::ptr::NonNull;
+use ffi::*;
+
+/// mimalloc does not expect blocks that are not word aligned, and fails
+/// assertions on them with the `debug` feature.
+#[inline]
+fn is_word_multiple(offset: usize) -> bool {
+    offset & (mem::size_of::<usize>() - 1) == 0
+}
+
+/// Wrap a block of `size` bytes returned by mimalloc, or return `None` if it
+/// is null.
+#[inline]
+fn tag_block(ptr: *mut c_void, size: usize) -> Option<NonNull<[u8]>> {
+    let block = NonNull::new(ptr as *mut u8)?;
+    Some(NonNull::slice_from_raw_parts(block, size))
+}
+
+impl MiMalloc {
+    /// Allocate `size` bytes such that the address at `offset` into the
+    /// block is aligned to `align`.
+    ///
+    /// This aligns the payload that follows a header of `offset` bytes,
+    /// rather than the header. The returned slice spans the `size` bytes
+    /// requested.
+    ///
+    /// Returns `None` if out of memory, if `align` is not a power of two, or
+    /// if `offset` is not a multiple of the word size.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use mimalloc::MiMalloc;
+    ///
+    /// // A 16 byte header followed by a 64 byte aligned payload.
+    /// let block = MiMalloc.alloc_aligned_at(16 + 1024, 64, 16).unwrap();
+    /// let payload = unsafe { block.cast::<u8>().as_ptr().add(16) };
+    /// assert_eq!(payload as usize % 64, 0);
+    /// unsafe { MiMalloc.free(block.cast().as_ptr()) };
+    /// ```
+    #[inline]
+    pub fn alloc_aligned_at(
+        &self,
+        size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Option<NonNull<[u8]>> {
+        if !is_word_multiple(offset) {
+            return None;
+        }
+        unsafe { tag_block(mi_malloc_aligned_at(size, align, offset), size) }
+    }
+
+    /// Allocate `size` zero-initialized bytes such that the address at
+    /// `offset` into the block is aligned to `align`.
+    ///
+    /// Returns `None` if out of memory, if `align` is not a power of two, or
+    /// if `offset` is not a multiple of the word size.
+    #[inline]
+    pub fn alloc_zeroed_aligned_at(
+        &self,
+        size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Option<NonNull<[u8]>> {
+        if !is_word_multiple(offset) {
+            return None;
+        }
+        unsafe { tag_block(mi_zalloc_aligned_at(size, align, offset), size) }
+    }
+
+    /// Allocate zero-initialized space for `count` elements of `size` bytes
+    /// such that the address at `offset` into the block is aligned to
+    /// `align`.
+    ///
+    /// Returns `None` if out of memory, if `count * size` overflows, if
+    /// `align` is not a power of two, or if `offset` is not a multiple of the
+    /// word size.
+    #[inline]
+    pub fn calloc_aligned_at(
+        &self,
+        count: usize,
+        size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Option<NonNull<[u8]>> {
+        if !is_word_multiple(offset) {
+            return None;
+        }
+        let len = count.checked_mul(size)?;
+        unsafe { tag_block(mi_calloc_aligned_at(count, size, align, offset), len) }
+    }
+
+    /// Re-allocate the block at `ptr` to `new_size` bytes, keeping the
+    /// address at `offset` aligned to `align`.
+    ///
+    /// Returns `None` if out of memory or if `offset` is not a multiple of
+    /// the word size, in which case `ptr` is not freed.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by mimalloc with the same `align` and
+    /// `offset`, or be null.
+    #[inline]
+    pub unsafe fn realloc_aligned_at(
+        &self,
+        ptr: *mut u8,
+        new_size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Option<NonNull<[u8]>> {
+        if !is_word_multiple(offset) {
+            return None;
+        }
+        tag_block(
+            mi_realloc_aligned_at(ptr as *mut c_void, new_size, align, offset),
+            new_size,
+        )
+    }
+
+    /// Free a block returned by one of the `_aligned_at` functions.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by mimalloc, or be null.
+    #[inline]
+    pub unsafe fn free(&self, ptr: *mut u8) {
+        mi_free(ptr as *mut c_void);
+    }
+}
+
+impl MiHeap {
+    /// Allocate `size` bytes out of this heap such that the address at
+    /// `offset` into the block is aligned to `align`.
+    ///
+    /// See [`MiMalloc::alloc_aligned_at`].
+    #[inline]
+    pub fn alloc_aligned_at(
+        &self,
+        size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Option<NonNull<[u8]>> {
+        if !is_word_multiple(offset) {
+            return None;
+        }
+        unsafe {
+            tag_block(
+                mi_heap_malloc_aligned_at(self.as_ptr(), size, align, offset),
+                size,
+            )
+        }
+    }
+
+    /// Allocate `size` zero-initialized bytes out of this heap such that the
+    /// address at `offset` into the block is aligned to `align`.
+    ///
+    /// See [`MiMalloc::alloc_zeroed_aligned_at`].
+    #[inline]
+    pub fn alloc_zeroed_aligned_at(
+        &self,
+        size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Option<NonNull<[u8]>> {
+        if !is_word_multiple(offset) {
+            return None;
+        }
+        unsafe {
+            tag_block(
+                mi_heap_zalloc_aligned_at(self.as_ptr(), size, align, offset),
+                size,
+            )
+        }
+    }
+
+    /// Allocate zero-initialized space for `count` elements of `size` bytes
+    /// out of this heap such that the address at `offset` into the block is
+    /// aligned to `align`.
+    ///
+    /// See [`MiMalloc::calloc_aligned_at`].
+    #[inline]
+    pub fn calloc_aligned_at(
+        &self,
+        count: usize,
+        size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Option<NonNull<[u8]>> {
+        if !is_word_multiple(offset) {
+            return None;
+        }
+        let len = count.checked_mul(size)?;
+        unsafe {
+            tag_block(
+                mi_heap_calloc_aligned_at(self.as_ptr(), count, size, align, offset),
+                len,
+            )
+        }
+    }
+
+    /// Re-allocate the block at `ptr` to `new_size` bytes in this heap,
+    /// keeping the address at `offset` aligned to `align`.
+    ///
+    /// Returns `None` if out of memory or if `offset` is not a multiple of
+    /// the word size, in which case `ptr` is not freed.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by mimalloc with the same `align` and
+    /// `offset`, or be null.
+    #[inline]
+    pub unsafe fn realloc_aligned_at(
+        &self,
+        ptr: *mut u8,
+        new_size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Option<NonNull<[u8]>> {
+        if !is_word_multiple(offset) {
+            return None;
+        }
+        tag_block(
+            mi_heap_realloc_aligned_at(self.as_ptr(), ptr as *mut c_void, new_size, align, offset),
+            new_size,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ops::ControlFlow;
+
+    fn payload(block: NonNull<[u8]>, offset: usize) -> usize {
+        block.cast::<u8>().as_ptr() as usize + offset
+    }
+
+    #[test]
+    fn it_aligns_the_payload() {
+        for (align, offset) in [(64, 16), (4096, 24), (32, 0), (16, 104)] {
+            let block = MiMalloc
+                .alloc_aligned_at(offset + 256, align, offset)
+                .unwrap();
+            assert_eq!(payload(block, offset) % align, 0);
+            assert_eq!(block.len(), offset + 256);
+            unsafe {
+                block.cast::<u8>().as_ptr().write_bytes(0xAB, block.len());
+                MiMalloc.free(block.cast().as_ptr());
+            }
+        }
+    }
+
+    #[test]
+    fn it_zeroes_and_reallocates() {
+        let (align, offset) = (128, 8);
+        let block = MiMalloc.alloc_zeroed_aligned_at(64, align, offset).unwrap();
+        assert_eq!(payload(block, offset) % align, 0);
+        unsafe {
+            let ptr = block.cast::<u8>().as_ptr();
+            assert!((0..64).all(|i| *ptr.add(i) == 0));
+            ptr.write_bytes(0x5A, 64);
+
+            let block = MiMalloc
+                .realloc_aligned_at(ptr, 1 << 16, align, offset)
+                .unwrap();
+            assert_eq!(payload(block, offset) % align, 0);
+            let ptr = block.cast::<u8>().as_ptr();
+            assert!((0..64).all(|i| *ptr.add(i) == 0x5A));
+            MiMalloc.free(ptr);
+        }
+    }
+
+    #[test]
+    fn it_callocs_arrays() {
+        let heap = MiHeap::new();
+        let (align, offset) = (64, 16);
+        for block in [
+            MiMalloc.calloc_aligned_at(10, 24, align, offset).unwrap(),
+            heap.calloc_aligned_at(10, 24, align, offset).unwrap(),
+        ] {
+            assert_eq!(payload(block, offset) % align, 0);
+            assert_eq!(block.len(), 240);
+            unsafe {
+                let ptr = block.cast::<u8>().as_ptr();
+                assert!((0..240).all(|i| *ptr.add(i) == 0));
+                MiMalloc.free(ptr);
+            }
+        }
+
+        assert!(MiMalloc
+            .calloc_aligned_at(usize::MAX / 2, 3, 64, 0)
+            .is_none());
+        assert!(heap.calloc_aligned_at(2, usize::MAX, 64, 0).is_none());
+        assert!(heap.calloc_aligned_at(1, 64, 64, 4).is_none());
+    }
+
+    #[test]
+    fn it_rejects_invalid_alignments() {
+        assert!(MiMalloc.alloc_aligned_at(64, 48, 8).is_none());
+        assert!(MiHeap::new().alloc_aligned_at(64, 0, 0).is_none());
+    }
+
+    #[test]
+    fn it_rejects_unaligned_offsets() {
+        assert!(MiMalloc.alloc_aligned_at(64, 64, 3).is_none());
+        assert!(MiMalloc.alloc_zeroed_aligned_at(64, 64, 100).is_none());
+        let heap = MiHeap::new();
+        assert!(heap.alloc_aligned_at(64, 64, 1).is_none());
+        assert!(heap.alloc_zeroed_aligned_at(64, 64, 12).is_none());
+        unsafe {
+            let block = MiMalloc.alloc_aligned_at(64, 64, 8).unwrap();
+            let ptr = block.cast::<u8>().as_ptr();
+            assert!(MiMalloc.realloc_aligned_at(ptr, 128, 64, 7).is_none());
+            assert!(heap.realloc_aligned_at(ptr, 128, 64, 7).is_none());
+            // The block is left untouched.
+            MiMalloc.free(ptr);
+        }
+    }
+
+    #[test]
+    fn it_aligns_the_payload_in_heaps() {
+        let heap = MiHeap::new();
+        let (align, offset) = (256, 40);
+        let block = heap.alloc_aligned_at(1000, align, offset).unwrap();
+        assert_eq!(payload(block, offset) % align, 0);
+        let ptr = block.cast::<u8>().as_ptr();
+        let mut found = false;
+        // Over-aligned blocks may start inside a larger mimalloc block.
+        let _ = heap.visit_blocks(|_, block, size| {
+            let start = block.as_ptr() as usize;
+            found |= (start..start + size).contains(&(ptr as usize));
+            ControlFlow::<()>::Continue(())
+        });
+        assert!(found);
+
+        let zeroed = heap.alloc_zeroed_aligned_at(100, align, offset).unwrap();
+        assert_eq!(payload(zeroed, offset) % align, 0);
+
+        unsafe {
+            let block = heap.realloc_aligned_at(ptr, 4000, align, offset).unwrap();
+            assert_eq!(payload(block, offset) % align, 0);
+            MiMalloc.free(block.cast().as_ptr());
+            MiMalloc.free(zeroed.cast().as_ptr());
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use crate::error::errno;
+use crate::MiHeap;
+use core::ffi::c_int;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Range;
+use ffi::*;
+
+/// A region of memory managed by mimalloc, from which heaps can allocate.
+///
+/// Arenas live until the process exits; `MiArena` is a cheap handle to one.
+/// An arena created as `exclusive` is only used by heaps created with
+/// [`MiArena::new_heap`], so it can pin a subsystem to pre-reserved memory.
+///
+/// ## Usage
+/// ```rust
+/// use core::alloc::Layout;
+/// use mimalloc::MiArena;
+///
+/// let arena = MiArena::reserve(64 << 20, false, false, true).unwrap();
+/// let heap = arena.new_heap();
+/// let layout = Layout::new::<u64>();
+/// let ptr = heap.alloc(layout);
+/// assert!(!ptr.is_null());
+/// unsafe { heap.dealloc(ptr, layout) };
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct MiArena {
+    id: mi_arena_id_t,
+}
+
+// Safety: an arena id is a process-wide handle that mimalloc accepts from any
+// thread.
+unsafe impl Send for MiArena {}
+unsafe impl Sync for MiArena {}
+
+/// Properties of a memory region handed to [`MiArena::manage`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MiRegionFlags {
+    /// The memory is already committed.
+    pub committed: bool,
+    /// The memory consists of large OS pages, or should never be decommitted
+    /// or protected (e.g. memory registered for RDMA).
+    pub large: bool,
+    /// The memory is known to be zeroed.
+    pub zeroed: bool,
+    /// The NUMA node the memory is associated with, if any.
+    pub numa_node: Option<u16>,
+    /// Only allow allocations from heaps created for this arena.
+    pub exclusive: bool,
+}
+
+/// An error creating an arena.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MiArenaError {
+    /// Not enough memory could be reserved from the OS (`ENOMEM`).
+    OutOfMemory,
+    /// mimalloc refused the region, e.g. because it is too small or
+    /// mimalloc cannot track more arenas.
+    Rejected,
+    /// The region handed to [`MiArena::from_region`] does not contain an
+    /// aligned range of at least the given number of bytes.
+    TooSmall(usize),
+    /// Reserving memory from the OS failed with another error code.
+    Os(c_int),
+}
+
+impl fmt::Display for MiArenaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiArenaError::OutOfMemory => f.write_str("out of memory reserving an arena"),
+            MiArenaError::Rejected => f.write_str("mimalloc rejected the arena region"),
+            MiArenaError::TooSmall(min) => {
+                write!(
+                    f,
+                    "the arena region holds no aligned range of {} bytes",
+                    min
+                )
+            }
+            MiArenaError::Os(code) => write!(f, "reserving an arena failed with error {}", code),
+        }
+    }
+}
+
+impl MiArena {
+    /// Reserve `size` bytes of OS memory as a new arena.
+    ///
+    /// - `commit`: commit the memory upfront.
+    /// - `allow_large`: allow large OS pages (2MiB) to be used.
+    /// - `exclusive`: only allow allocations from heaps created for this
+    ///   arena.
+    pub fn reserve(
+        size: usize,
+        commit: bool,
+        allow_large: bool,
+        exclusive: bool,
+    ) -> Result<MiArena, MiArenaError> {
+        let mut id = MaybeUninit::<mi_arena_id_t>::uninit();
+        // mimalloc v2 crashes when reserving memory before the calling thread
+        // is initialized.
+        unsafe { mi_thread_init() };
+        let code = unsafe {
+            mi_reserve_os_memory_ex(size, commit, allow_large, exclusive, id.as_mut_ptr())
+        };
+        match code {
+            0 => Ok(MiArena {
+                id: unsafe { id.assume_init() },
+            }),
+            errno::ENOMEM => Err(MiArenaError::OutOfMemory),
+            code => Err(MiArenaError::Os(code)),
+        }
+    }
+
+    /// Let mimalloc manage the memory at `start` of `size` bytes as a new
+    /// arena.
+    ///
+    /// # Safety
+    /// The memory must be valid for reads and writes, must not be used
+    /// for anything else for the rest of the process, and `start` and `size`
+    /// must satisfy mimalloc's alignment and minimum size requirements;
+    /// mimalloc is likely to crash otherwise. [`MiArena::from_region`] checks
+    /// these requirements.
+    pub unsafe fn manage(
+        start: *mut u8,
+        size: usize,
+        flags: MiRegionFlags,
+    ) -> Result<MiArena, MiArenaError> {
+        let mut id = MaybeUninit::<mi_arena_id_t>::uninit();
+        mi_thread_init();
+        let numa_node = flags.numa_node.map_or(-1, c_int::from);
+        let managed = mi_manage_os_memory_ex(
+            start as *const _,
+            size,
+            flags.committed,
+            flags.large,
+            flags.zeroed,
+            numa_node,
+            flags.exclusive,
+            id.as_mut_ptr(),
+        );
+        if managed {
+            Ok(MiArena {
+                id: id.assume_init(),
+            })
+        } else {
+            Err(MiArenaError::Rejected)
+        }
+    }
+
+    /// Let mimalloc manage `region` as a new arena.
+    ///
+    /// mimalloc requires the start of an arena to be aligned and its size to
+    /// be a multiple of its arena granularity, so only the largest such range
+    /// inside `region` is used; [`MiArena::usable_span`] returns it upfront,
+    /// and [`MiArena::area`] afterwards. The rest of `region` stays unused.
+    ///
+    /// `flags.zeroed` is ignored since it cannot be checked, and a wrong
+    /// claim would make zero-initializing allocations return garbage; use
+    /// [`MiArena::manage`] to pass it.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use mimalloc::{MiArena, MiRegionFlags};
+    ///
+    /// let region = Box::leak(Box::new_uninit_slice(160 << 20));
+    /// let span = MiArena::usable_span(region).unwrap();
+    /// let arena = MiArena::from_region(region, MiRegionFlags::default()).unwrap();
+    /// assert_eq!(arena.area().1, span.len());
+    /// ```
+    pub fn from_region(
+        region: &'static mut [MaybeUninit<u8>],
+        flags: MiRegionFlags,
+    ) -> Result<MiArena, MiArenaError> {
+        let span = match MiArena::usable_span(region) {
+            Some(span) => span,
+            None => return Err(MiArenaError::TooSmall(region_granularity().1)),
+        };
+        let flags = MiRegionFlags {
+            zeroed: false,
+            ..flags
+        };
+        // Safety: the region is exclusively borrowed for the rest of the
+        // process, and `span` satisfies mimalloc's requirements.
+        unsafe {
+            let start = region.as_mut_ptr().add(span.start) as *mut u8;
+            MiArena::manage(start, span.len(), flags)
+        }
+    }
+
+    /// Return the byte range of `region` that [`MiArena::from_region`] would
+    /// hand to mimalloc, or `None` if it is too small to hold an arena.
+    pub fn usable_span(region: &[MaybeUninit<u8>]) -> Option<Range<usize>> {
+        let (align, min_size) = region_granularity();
+        let offset = (region.as_ptr() as usize).wrapping_neg() & (align - 1);
+        let size = region.len().checked_sub(offset)?;
+        let size = size - size % min_size;
+        if size == 0 {
+            return None;
+        }
+        Some(offset..offset + size)
+    }
+
+    /// Return the start and size in bytes of the memory of this arena.
+    pub fn area(&self) -> (*mut u8, usize) {
+        let mut size = 0;
+        let start = unsafe { mi_arena_area(self.id, &mut size) };
+        (start as *mut u8, size)
+    }
+
+    /// Return the underlying `mi_arena_id_t` for use with the raw FFI.
+    #[inline]
+    pub fn as_raw(&self) -> mi_arena_id_t {
+        self.id
+    }
+
+    /// Create a new heap that only allocates in this arena.
+    ///
+    /// # Panics
+    /// Panics if mimalloc fails to create the heap, see
+    /// [`MiArena::try_new_heap`] for a non-panicking variant.
+    pub fn new_heap(&self) -> MiHeap {
+        self.try_new_heap()
+            .expect("mimalloc failed to create a heap in arena")
+    }
+
+    /// Create a new heap that only allocates in this arena, or return `None`
+    /// if mimalloc failed to create it.
+    pub fn try_new_heap(&self) -> Option<MiHeap> {
+        MiHeap::from_raw(unsafe { mi_heap_new_in_arena(self.id) })
+    }
+}
+
+/// Return the alignment of the start of an arena and the granularity of its
+/// size, which is also the minimum size.
+fn region_granularity() -> (usize, usize) {
+    #[cfg(not(feature = "v2"))]
+    {
+        unsafe { (mi_arena_min_alignment(), mi_arena_min_size()) }
+    }
+    // v2 aligns arenas to `MI_SEGMENT_ALIGN` and splits them in blocks of
+    // `MI_ARENA_BLOCK_SIZE`, both the segment size.
+    #[cfg(feature = "v2")]
+    {
+        #[cfg(target_pointer_width = "64")]
+        const MI_SEGMENT_SIZE: usize = 32 << 20;
+        #[cfg(not(target_pointer_width = "64"))]
+        const MI_SEGMENT_SIZE: usize = 4 << 20;
+        (MI_SEGMENT_SIZE, MI_SEGMENT_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::alloc::{GlobalAlloc, Layout};
+    use std::alloc::System;
+
+    #[test]
+    fn it_allocates_in_reserved_arena() {
+        let arena = MiArena::reserve(64 << 20, false, false, true).unwrap();
+        let heap = arena.new_heap();
+        let layout = Layout::from_size_align(1024, 16).unwrap();
+
+        let ptr = heap.alloc(layout);
+        let (start, size) = arena.area();
+        assert!(size >= 64 << 20);
+        assert!(ptr >= start && ptr < start.wrapping_add(size));
+        unsafe {
+            ptr.write_bytes(0xCD, layout.size());
+            heap.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn it_allocates_in_managed_arena() {
+        let size = 64 << 20;
+        let region = Layout::from_size_align(size, 32 << 20).unwrap();
+        unsafe {
+            let start = System.alloc_zeroed(region);
+            assert!(!start.is_null());
+            let flags = MiRegionFlags {
+                committed: true,
+                zeroed: true,
+                exclusive: true,
+                ..MiRegionFlags::default()
+            };
+            // The region is never returned to `System`.
+            let arena = MiArena::manage(start, size, flags).unwrap();
+
+            let heap = arena.new_heap();
+            let layout = Layout::from_size_align(256, 8).unwrap();
+            let ptr = heap.alloc(layout);
+            assert!(ptr >= start && ptr < start.add(size));
+            heap.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn it_trims_regions() {
+        let (align, min_size) = region_granularity();
+        assert!(align.is_power_of_two());
+        assert!(min_size >= align);
+
+        let layout = Layout::from_size_align(3 * min_size, align).unwrap();
+        unsafe {
+            let start = System.alloc(layout) as *mut MaybeUninit<u8>;
+            assert!(!start.is_null());
+            let aligned = core::slice::from_raw_parts(start, layout.size());
+            assert_eq!(MiArena::usable_span(aligned), Some(0..3 * min_size));
+            let misaligned = core::slice::from_raw_parts(start.add(1), layout.size() - 1);
+            assert_eq!(
+                MiArena::usable_span(misaligned),
+                Some(align - 1..align - 1 + 2 * min_size)
+            );
+            let tiny = core::slice::from_raw_parts(start.add(1), min_size);
+            assert_eq!(MiArena::usable_span(tiny), None);
+            System.dealloc(start as *mut u8, layout);
+        }
+    }
+
+    #[test]
+    fn it_allocates_in_region() {
+        let (align, min_size) = region_granularity();
+        let size = 2 * min_size + align / 2;
+        let region = Layout::from_size_align(size, 8).unwrap();
+        let region = unsafe {
+            let start = System.alloc(region) as *mut MaybeUninit<u8>;
+            assert!(!start.is_null());
+            // Leaked, since the region is used for the rest of the process.
+            core::slice::from_raw_parts_mut(start, size)
+        };
+        let start = region.as_mut_ptr() as *mut u8;
+        let span = MiArena::usable_span(region).unwrap();
+        assert!(span.len() >= min_size);
+
+        let flags = MiRegionFlags {
+            exclusive: true,
+            ..MiRegionFlags::default()
+        };
+        let arena = MiArena::from_region(region, flags).unwrap();
+        let (area_start, area_size) = arena.area();
+        assert_eq!(area_start, start.wrapping_add(span.start));
+        assert_eq!(area_size, span.len());
+
+        let heap = arena.new_heap();
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let ptr = heap.alloc_zeroed(layout);
+        assert!(ptr >= area_start && ptr < area_start.wrapping_add(area_size));
+        unsafe {
+            assert!((0..layout.size()).all(|i| *ptr.add(i) == 0));
+            heap.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn it_rejects_small_regions() {
+        let region = std::boxed::Box::leak(std::boxed::Box::new([MaybeUninit::uninit(); 4096]));
+        let result = MiArena::from_region(region, MiRegionFlags::default());
+        assert_eq!(
+            result.unwrap_err(),
+            MiArenaError::TooSmall(region_granularity().1)
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use crate::dispatch::is_natural;
+use crate::MiMalloc;
+use core::alloc::Layout;
+use core::ffi::c_void;
+use core::fmt;
+use core::mem;
+use core::ptr::NonNull;
+use ffi::*;
+
+/// An error allocating an array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MiAllocError {
+    /// The size of the array overflows `isize::MAX` bytes (`EOVERFLOW`).
+    Overflow,
+    /// Not enough memory was available (`ENOMEM`).
+    OutOfMemory,
+}
+
+impl fmt::Display for MiAllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiAllocError::Overflow => f.write_str("array size overflow"),
+            MiAllocError::OutOfMemory => f.write_str("out of memory allocating an array"),
+        }
+    }
+}
+
+/// Wrap an array of `count` elements returned by mimalloc, or report out of
+/// memory if it is null.
+#[inline]
+fn tag_array<T>(ptr: *mut c_void, count: usize) -> Result<NonNull<[T]>, MiAllocError> {
+    let ptr = NonNull::new(ptr as *mut T).ok_or(MiAllocError::OutOfMemory)?;
+    Ok(NonNull::slice_from_raw_parts(ptr, count))
+}
+
+impl MiMalloc {
+    /// Allocate an uninitialized array of `count` elements of `T`.
+    ///
+    /// Unlike computing `count * size_of::<T>()` by hand, this reports an
+    /// overflowing size as [`MiAllocError::Overflow`], which makes it safe to
+    /// use with untrusted lengths. The array must be freed with
+    /// [`MiMalloc::free`].
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use mimalloc::{MiAllocError, MiMalloc};
+    ///
+    /// let array = MiMalloc.alloc_array::<u32>(100).unwrap();
+    /// assert_eq!(array.len(), 100);
+    /// unsafe { MiMalloc.free(array.cast().as_ptr()) };
+    ///
+    /// let hostile = usize::MAX / 2;
+    /// assert_eq!(MiMalloc.alloc_array::<u32>(hostile), Err(MiAllocError::Overflow));
+    /// ```
+    #[inline]
+    pub fn alloc_array<T>(&self, count: usize) -> Result<NonNull<[T]>, MiAllocError> {
+        let layout = Layout::array::<T>(count).map_err(|_| MiAllocError::Overflow)?;
+        let ptr = unsafe {
+            if is_natural(layout.size(), layout.align()) {
+                mi_mallocn(count, mem::size_of::<T>())
+            } else {
+                mi_malloc_aligned(layout.size(), layout.align())
+            }
+        };
+        tag_array(ptr, count)
+    }
+
+    /// Allocate a zero-initialized array of `count` elements of `T`.
+    ///
+    /// See [`MiMalloc::alloc_array`].
+    #[inline]
+    pub fn alloc_array_zeroed<T>(&self, count: usize) -> Result<NonNull<[T]>, MiAllocError> {
+        let layout = Layout::array::<T>(count).map_err(|_| MiAllocError::Overflow)?;
+        let ptr = unsafe {
+            if is_natural(layout.size(), layout.align()) {
+                mi_calloc(count, mem::size_of::<T>())
+            } else {
+                mi_calloc_aligned(count, mem::size_of::<T>(), layout.align())
+            }
+        };
+        tag_array(ptr, count)
+    }
+
+    /// Re-allocate the array at `ptr` to `new_count` elements of `T`.
+    ///
+    /// Elements up to the smaller of the old and new count are kept, any new
+    /// ones are uninitialized. On error `ptr` is not freed.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by [`MiMalloc::alloc_array`],
+    /// [`MiMalloc::alloc_array_zeroed`] or [`MiMalloc::realloc_array`] for
+    /// the same `T`, or be null.
+    #[inline]
+    pub unsafe fn realloc_array<T>(
+        &self,
+        ptr: *mut T,
+        new_count: usize,
+    ) -> Result<NonNull<[T]>, MiAllocError> {
+        let layout = Layout::array::<T>(new_count).map_err(|_| MiAllocError::Overflow)?;
+        let ptr = if is_natural(layout.size(), layout.align()) {
+            mi_reallocn(ptr as *mut c_void, new_count, mem::size_of::<T>())
+        } else {
+            mi_realloc_aligned(ptr as *mut c_void, layout.size(), layout.align())
+        };
+        tag_array(ptr, new_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(align(64))]
+    struct Line([u8; 64]);
+
+    #[test]
+    fn it_allocates_arrays() {
+        let array = MiMalloc.alloc_array::<u64>(1000).unwrap();
+        assert_eq!(array.len(), 1000);
+        let ptr = array.cast::<u64>().as_ptr();
+        assert_eq!(ptr as usize % mem::align_of::<u64>(), 0);
+        assert!(unsafe { mi_usable_size(ptr as *const c_void) } >= 8000);
+        unsafe { MiMalloc.free(ptr as *mut u8) };
+
+        let lines = MiMalloc.alloc_array::<Line>(3).unwrap();
+        assert_eq!(lines.cast::<Line>().as_ptr() as usize % 64, 0);
+        unsafe { MiMalloc.free(lines.cast().as_ptr()) };
+
+        let empty = MiMalloc.alloc_array::<u32>(0).unwrap();
+        assert_eq!(empty.len(), 0);
+        unsafe { MiMalloc.free(empty.cast().as_ptr()) };
+    }
+
+    #[test]
+    fn it_zeroes_arrays() {
+        for count in [7, 10_000] {
+            let array = MiMalloc.alloc_array_zeroed::<u32>(count).unwrap();
+            unsafe {
+                assert!(array.as_ref().iter().all(|&x| x == 0));
+                MiMalloc.free(array.cast().as_ptr());
+            }
+        }
+        let lines = MiMalloc.alloc_array_zeroed::<Line>(5).unwrap();
+        unsafe {
+            assert!(lines.as_ref().iter().all(|line| line.0 == [0; 64]));
+            MiMalloc.free(lines.cast().as_ptr());
+        }
+    }
+
+    #[test]
+    fn it_reallocates_arrays() {
+        unsafe {
+            let array = MiMalloc.alloc_array::<u16>(4).unwrap();
+            let ptr = array.cast::<u16>().as_ptr();
+            for i in 0..4 {
+                ptr.add(i).write(i as u16);
+            }
+            let array = MiMalloc.realloc_array(ptr, 5000).unwrap();
+            assert_eq!(array.len(), 5000);
+            let ptr = array.cast::<u16>().as_ptr();
+            assert!((0..4).all(|i| *ptr.add(i) == i as u16));
+
+            let lines = MiMalloc
+                .realloc_array::<Line>(core::ptr::null_mut(), 2)
+                .unwrap();
+            let lines = MiMalloc
+                .realloc_array(lines.cast::<Line>().as_ptr(), 20)
+                .unwrap();
+            assert_eq!(lines.cast::<Line>().as_ptr() as usize % 64, 0);
+
+            MiMalloc.free(ptr as *mut u8);
+            MiMalloc.free(lines.cast().as_ptr());
+        }
+    }
+
+    #[test]
+    fn it_reports_overflow() {
+        assert_eq!(
+            MiMalloc.alloc_array::<u64>(usize::MAX / 4),
+            Err(MiAllocError::Overflow)
+        );
+        assert_eq!(
+            MiMalloc.alloc_array_zeroed::<Line>(usize::MAX / 64 + 1),
+            Err(MiAllocError::Overflow)
+        );
+        unsafe {
+            let array = MiMalloc.alloc_array::<u32>(4).unwrap();
+            let ptr = array.cast::<u32>().as_ptr();
+            assert_eq!(
+                MiMalloc.realloc_array(ptr, usize::MAX),
+                Err(MiAllocError::Overflow)
+            );
+            // The array is left untouched.
+            MiMalloc.free(ptr as *mut u8);
+        }
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn it_reports_out_of_memory() {
+        // Fits in `isize::MAX`, but not in any address space.
+        let count = isize::MAX as usize / 2;
+        assert_eq!(
+            MiMalloc.alloc_array::<u8>(count),
+            Err(MiAllocError::OutOfMemory)
+        );
+    }
+}
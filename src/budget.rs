@@ -0,0 +1,191 @@
+use crate::{MiArena, MiArenaError, MiHeap};
+use core::alloc::Layout;
+use core::ops::ControlFlow;
+
+/// A heap with a hard memory budget, backed by its own exclusive arena.
+///
+/// All allocations are served from an arena reserved up front, and fail
+/// instead of falling back to the OS once it is exhausted. With the
+/// `nightly_allocator_api` feature `BudgetedHeap` implements `Allocator`, so
+/// exhaustion surfaces as `AllocError`.
+///
+/// The budget is enforced at arena granularity: mimalloc rounds the arena up
+/// to whole arena slices, so the enforced cap is
+/// [`capacity`](BudgetedHeap::capacity), which may exceed the requested
+/// [`budget`](BudgetedHeap::budget).
+///
+/// # Leaks
+/// mimalloc cannot release an arena, so dropping a `BudgetedHeap` frees its
+/// heap but keeps the arena's address space reserved for the rest of the
+/// process, and its slot in mimalloc's fixed-size arena table taken. Create
+/// budgeted heaps for long-lived consumers and reuse them, rather than one
+/// per request or short-lived tenant.
+///
+/// ## Usage
+/// ```rust
+/// use core::alloc::Layout;
+/// use mimalloc::BudgetedHeap;
+///
+/// let heap = BudgetedHeap::new(64 << 20).unwrap();
+/// let layout = Layout::from_size_align(128 << 20, 8).unwrap();
+/// assert!(heap.alloc(layout).is_null());
+/// ```
+#[derive(Debug)]
+pub struct BudgetedHeap {
+    pub(crate) heap: MiHeap,
+    arena: MiArena,
+    budget: usize,
+}
+
+impl BudgetedHeap {
+    /// Reserve an exclusive arena of at least `budget` bytes and create a heap
+    /// in it.
+    ///
+    /// The memory is reserved but not committed upfront. The arena is rounded
+    /// up to mimalloc's arena granularity, see
+    /// [`capacity`](BudgetedHeap::capacity) for the cap actually enforced.
+    ///
+    /// The arena is never released, even once the `BudgetedHeap` is dropped,
+    /// see [Leaks](BudgetedHeap#leaks).
+    pub fn new(budget: usize) -> Result<Self, MiArenaError> {
+        let arena = MiArena::reserve(budget, false, false, true)?;
+        let heap = arena.try_new_heap().ok_or(MiArenaError::OutOfMemory)?;
+        Ok(BudgetedHeap {
+            heap,
+            arena,
+            budget,
+        })
+    }
+
+    /// Return the budget requested from [`BudgetedHeap::new`].
+    #[inline]
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Return the heap that allocates in the budgeted arena.
+    #[inline]
+    pub fn heap(&self) -> &MiHeap {
+        &self.heap
+    }
+
+    /// Return the exclusive arena backing this heap.
+    #[inline]
+    pub fn arena(&self) -> MiArena {
+        self.arena
+    }
+
+    /// Total bytes of the arena backing this heap, which is the cap actually
+    /// enforced.
+    ///
+    /// At least [`budget`](BudgetedHeap::budget), rounded up to mimalloc's
+    /// arena granularity.
+    pub fn capacity(&self) -> usize {
+        self.arena.area().1
+    }
+
+    /// Bytes of the arena taken by pages of this heap.
+    ///
+    /// This does not include mimalloc's meta-data, so less than
+    /// [`remaining`](BudgetedHeap::remaining) may be available in practice.
+    pub fn used(&self) -> usize {
+        let mut used = 0;
+        let _ = self.heap.visit_areas(|area| {
+            used += area.reserved();
+            ControlFlow::<()>::Continue(())
+        });
+        used
+    }
+
+    /// Bytes of the arena not yet taken by pages of this heap.
+    pub fn remaining(&self) -> usize {
+        self.capacity().saturating_sub(self.used())
+    }
+
+    /// Allocate memory described by `layout` within the budget.
+    ///
+    /// Returns null if the budget is exhausted.
+    #[inline]
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap.alloc(layout)
+    }
+
+    /// Allocate zero-initialized memory described by `layout` within the
+    /// budget.
+    ///
+    /// Returns null if the budget is exhausted.
+    #[inline]
+    pub fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.heap.alloc_zeroed(layout)
+    }
+
+    /// Re-allocate memory to `new_size` bytes within the budget.
+    ///
+    /// Returns null if the budget is exhausted, in which case `ptr` is not
+    /// freed.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this heap with `layout`, or be null.
+    #[inline]
+    pub unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.heap.realloc(ptr, layout, new_size)
+    }
+
+    /// Free memory previously allocated by this heap.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by mimalloc with `layout`, or be null.
+    #[inline]
+    pub unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_holds_the_budget() {
+        let heap = BudgetedHeap::new(32 << 20).unwrap();
+        let capacity = heap.capacity();
+        assert_eq!(heap.budget(), 32 << 20);
+        assert!(capacity >= heap.budget());
+        assert_eq!(heap.remaining(), capacity);
+
+        let (start, size) = heap.arena().area();
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let mut allocated = 0;
+        loop {
+            let ptr = heap.alloc(layout);
+            if ptr.is_null() {
+                break;
+            }
+            assert!(ptr >= start && ptr < start.wrapping_add(size));
+            allocated += layout.size();
+            assert!(allocated <= capacity, "allocated beyond the budget");
+        }
+        assert!(allocated > capacity / 2);
+        assert!(heap.remaining() < capacity / 2);
+
+        // Nothing is served from outside the arena once it is exhausted.
+        assert!(heap.alloc(Layout::new::<u8>()).is_null());
+        assert!(heap.alloc_zeroed(layout).is_null());
+    }
+
+    #[test]
+    fn it_rejects_allocations_over_budget() {
+        let heap = BudgetedHeap::new(32 << 20).unwrap();
+        let layout = Layout::from_size_align(heap.capacity() + 1, 8).unwrap();
+        assert!(heap.alloc(layout).is_null());
+
+        let small = Layout::from_size_align(64, 8).unwrap();
+        let ptr = heap.alloc(small);
+        assert!(!ptr.is_null());
+        assert!(heap.remaining() < heap.capacity());
+        unsafe {
+            assert!(heap.realloc(ptr, small, heap.capacity() + 1).is_null());
+            heap.dealloc(ptr, small);
+        }
+    }
+}
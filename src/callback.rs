@@ -0,0 +1,60 @@
+//! Storage for Rust closures registered as mimalloc callbacks.
+//!
+//! mimalloc stores a callback and its argument in two separate atomics, so a
+//! concurrent call may pair a newly registered function with the previous
+//! argument. Registrations therefore always use a single non-generic
+//! trampoline, and the argument carries its own typed entry point.
+
+use core::ffi::c_void;
+use core::mem;
+use ffi::*;
+
+#[repr(C)]
+struct Callback<A, F> {
+    call: unsafe fn(*const c_void, A),
+    f: F,
+}
+
+/// Move `f` into a never freed mimalloc allocation and return the argument to
+/// register alongside a trampoline that calls [`invoke`].
+///
+/// The allocation is never freed since mimalloc may still be calling it after
+/// another callback is registered.
+///
+/// # Panics
+/// Panics if mimalloc fails to allocate room for the callback.
+pub(crate) fn leak<A, F>(f: F, call: unsafe fn(*const c_void, A)) -> *mut c_void {
+    let size = mem::size_of::<Callback<A, F>>();
+    let align = mem::align_of::<Callback<A, F>>();
+    let ptr = unsafe { mi_malloc_aligned(size, align) as *mut Callback<A, F> };
+    if ptr.is_null() {
+        panic!("mimalloc failed to allocate a callback");
+    }
+    // Safety: `ptr` is a fresh allocation suitable for a `Callback`.
+    unsafe { ptr.write(Callback { call, f }) };
+    ptr as *mut c_void
+}
+
+/// Return the closure stored at `arg` by [`leak`].
+///
+/// # Safety
+/// `arg` must have been returned by [`leak`] for an `F`.
+pub(crate) unsafe fn get<'a, A, F>(arg: *const c_void) -> &'a F {
+    &(*(arg as *const Callback<A, F>)).f
+}
+
+/// Call the closure stored at `arg` by [`leak`], doing nothing if `arg` is
+/// null.
+///
+/// # Safety
+/// `arg` must have been returned by [`leak`] for an argument of type `A`, or
+/// be null.
+pub(crate) unsafe fn invoke<A>(arg: *mut c_void, value: A) {
+    if arg.is_null() {
+        return;
+    }
+    // Safety: `call` is the first field of the `#[repr(C)]` `Callback`, and
+    // its type only depends on `A`.
+    let call = *(arg as *const unsafe fn(*const c_void, A));
+    call(arg, value);
+}
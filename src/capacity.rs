@@ -0,0 +1,157 @@
+extern crate alloc;
+
+use crate::MiMalloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
+use ffi::*;
+
+impl MiMalloc {
+    /// Return the number of bytes mimalloc actually allocates for a request
+    /// of `size` bytes with at most the default alignment, which is at least
+    /// `size`.
+    ///
+    /// Requesting the returned size uses space that would otherwise be wasted
+    /// in the size class of the allocation. Over-aligned requests may be
+    /// placed in larger blocks.
+    #[inline]
+    pub fn good_size(&self, size: usize) -> usize {
+        unsafe { mi_good_size(size) }
+    }
+}
+
+/// Capacity helpers for collections that round up to mimalloc's size
+/// classes, so buffers use the slack mimalloc would otherwise waste.
+///
+/// These only pay off with [`MiMalloc`] as the global allocator.
+///
+/// ## Usage
+/// ```rust
+/// use mimalloc::GoodCapacity;
+///
+/// let mut buf: Vec<u8> = Vec::with_good_capacity(100);
+/// assert!(buf.capacity() >= 100);
+/// buf.reserve_good(1000);
+/// assert!(buf.capacity() >= 1000);
+/// ```
+pub trait GoodCapacity {
+    /// Create an empty collection with room for at least `capacity` elements,
+    /// rounded up to fill mimalloc's size class.
+    fn with_good_capacity(capacity: usize) -> Self;
+
+    /// Reserve room for at least `additional` more elements, rounded up to
+    /// fill mimalloc's size class.
+    ///
+    /// Like `reserve`, this grows the capacity at least geometrically to
+    /// avoid frequent re-allocations.
+    ///
+    /// # Panics
+    /// Panics if the new capacity overflows `isize::MAX` bytes.
+    fn reserve_good(&mut self, additional: usize);
+}
+
+impl<T> GoodCapacity for Vec<T> {
+    fn with_good_capacity(capacity: usize) -> Self {
+        Vec::with_capacity(good_capacity::<T>(capacity))
+    }
+
+    fn reserve_good(&mut self, additional: usize) {
+        let target = grown_capacity::<T>(self.len(), self.capacity(), additional);
+        self.reserve_exact(target - self.len());
+    }
+}
+
+impl GoodCapacity for String {
+    fn with_good_capacity(capacity: usize) -> Self {
+        String::with_capacity(good_capacity::<u8>(capacity))
+    }
+
+    fn reserve_good(&mut self, additional: usize) {
+        let target = grown_capacity::<u8>(self.len(), self.capacity(), additional);
+        self.reserve_exact(target - self.len());
+    }
+}
+
+/// Round `capacity` elements of `T` up to fill a mimalloc size class.
+fn good_capacity<T>(capacity: usize) -> usize {
+    let size = mem::size_of::<T>();
+    if size == 0 || capacity == 0 {
+        return capacity;
+    }
+    match capacity.checked_mul(size) {
+        Some(bytes) => (unsafe { mi_good_size(bytes) } / size).max(capacity),
+        // Let the collection report the overflow.
+        None => capacity,
+    }
+}
+
+/// The capacity to grow to for `additional` more elements, or the current
+/// one if they fit already.
+fn grown_capacity<T>(len: usize, capacity: usize, additional: usize) -> usize {
+    let required = match len.checked_add(additional) {
+        Some(required) => required,
+        // Let the collection report the overflow.
+        None => usize::MAX,
+    };
+    if required <= capacity {
+        return capacity;
+    }
+    good_capacity::<T>(required.max(capacity.saturating_mul(2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_rounds_to_size_classes() {
+        let good = MiMalloc.good_size(100);
+        assert!(good >= 100);
+
+        // Debug builds of mimalloc pad blocks, so the usable size may fall
+        // short of the size class.
+        let ptr = unsafe { mi_malloc(100) };
+        let usable = unsafe { mi_usable_size(ptr) };
+        assert!((100..=good).contains(&usable));
+        unsafe { mi_free(ptr) };
+
+        assert_eq!(good_capacity::<u8>(100), good);
+        assert_eq!(good_capacity::<[u8; 3]>(33), good / 3);
+        assert_eq!(good_capacity::<()>(5), 5);
+        assert_eq!(good_capacity::<u64>(0), 0);
+        assert_eq!(good_capacity::<u64>(usize::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn it_creates_vecs_with_good_capacity() {
+        let v: Vec<u32> = Vec::with_good_capacity(25);
+        assert_eq!(v.capacity(), good_capacity::<u32>(25));
+        assert!(v.capacity() >= 25);
+
+        let s = String::with_good_capacity(100);
+        assert_eq!(s.capacity(), good_capacity::<u8>(100));
+    }
+
+    #[test]
+    fn it_reserves_good_capacity() {
+        let mut v: Vec<u16> = Vec::new();
+        v.reserve_good(10);
+        assert_eq!(v.capacity(), good_capacity::<u16>(10));
+
+        // Fits already.
+        let capacity = v.capacity();
+        v.extend_from_slice(&[1, 2, 3]);
+        v.reserve_good(capacity - v.len());
+        assert_eq!(v.capacity(), capacity);
+
+        // Grows at least geometrically.
+        v.reserve_good(capacity);
+        assert!(v.capacity() >= 2 * capacity);
+        assert_eq!(v.capacity(), good_capacity::<u16>(2 * capacity));
+
+        let mut s = String::from("hello");
+        s.reserve_good(200);
+        assert!(s.capacity() >= 205);
+        assert_eq!(&s, "hello");
+    }
+}
@@ -0,0 +1,120 @@
+use crate::callback;
+use core::ffi::{c_ulonglong, c_void};
+use ffi::*;
+
+/// Arguments of a deferred free callback, see [`register_deferred_free`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DeferredFree {
+    /// If true, all outstanding items should be freed.
+    pub force: bool,
+    /// A per-thread count that increases monotonically and is deterministic
+    /// if the program allocates deterministically.
+    pub heartbeat: u64,
+}
+
+/// Register `f` to be called by mimalloc to free memory that was kept alive
+/// for a while, such as objects queued by a reference counting runtime.
+///
+/// mimalloc calls `f` deterministically after some number of allocations,
+/// regardless of freeing or available free memory, and with
+/// [`DeferredFree::force`] set when collecting with `force`. It may be called
+/// on any thread, possibly concurrently, and must not panic; a panic aborts
+/// the process.
+///
+/// mimalloc does not invoke the callback recursively on a thread, so `f`
+/// may itself allocate and free memory through [`MiMalloc`](crate::MiMalloc)
+/// without being re-entered.
+///
+/// At most one callback is active; registering another one replaces it. The
+/// callback is moved into a mimalloc allocation that is never freed, since
+/// mimalloc may still be calling it after it was replaced.
+///
+/// # Panics
+/// Panics if mimalloc fails to allocate room for the callback.
+///
+/// ## Usage
+/// ```rust
+/// use mimalloc::DeferredFree;
+///
+/// mimalloc::register_deferred_free(|deferred: DeferredFree| {
+///     if deferred.force {
+///         // Drop everything that is queued.
+///     }
+/// });
+/// mimalloc::unregister_deferred_free();
+/// ```
+pub fn register_deferred_free<F>(f: F)
+where
+    F: Fn(DeferredFree) + Send + Sync + 'static,
+{
+    let arg = callback::leak(f, call_deferred_free::<F>);
+    unsafe { mi_register_deferred_free(Some(deferred_free), arg) }
+}
+
+/// Remove the callback registered with [`register_deferred_free`].
+pub fn unregister_deferred_free() {
+    unsafe { mi_register_deferred_free(None, core::ptr::null_mut()) }
+}
+
+unsafe extern "C" fn deferred_free(force: bool, heartbeat: c_ulonglong, arg: *mut c_void) {
+    callback::invoke(arg, DeferredFree { force, heartbeat });
+}
+
+unsafe fn call_deferred_free<F: Fn(DeferredFree)>(arg: *const c_void, deferred: DeferredFree) {
+    callback::get::<DeferredFree, F>(arg)(deferred)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::MiMalloc;
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::cell::Cell;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    std::thread_local! {
+        static DEPTH: Cell<usize> = const { Cell::new(0) };
+    }
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static FORCED: AtomicBool = AtomicBool::new(false);
+    static NESTED: AtomicBool = AtomicBool::new(false);
+
+    // The callback is process-wide, so registration and unregistration are
+    // tested together.
+    #[test]
+    fn it_calls_deferred_free() {
+        register_deferred_free(|deferred: DeferredFree| {
+            // Threads exiting concurrently may run the callback after their
+            // thread locals were destroyed.
+            let _ = DEPTH.try_with(|depth| {
+                if depth.get() > 0 {
+                    NESTED.store(true, Ordering::Relaxed);
+                }
+                depth.set(depth.get() + 1);
+                // Allocating from the callback must not re-enter it.
+                let layout = Layout::from_size_align(1 << 16, 8).unwrap();
+                for _ in 0..64 {
+                    unsafe { MiMalloc.dealloc(MiMalloc.alloc(layout), layout) };
+                }
+                depth.set(depth.get() - 1);
+            });
+            if deferred.force {
+                FORCED.store(true, Ordering::Relaxed);
+            }
+            CALLS.fetch_add(1, Ordering::Relaxed);
+        });
+
+        unsafe { mi_collect(true) };
+        assert!(CALLS.load(Ordering::Relaxed) > 0);
+        assert!(FORCED.load(Ordering::Relaxed));
+        assert!(!NESTED.load(Ordering::Relaxed));
+
+        unregister_deferred_free();
+        let calls = CALLS.load(Ordering::Relaxed);
+        unsafe { mi_collect(true) };
+        assert_eq!(CALLS.load(Ordering::Relaxed), calls);
+    }
+}
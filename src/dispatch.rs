@@ -0,0 +1,238 @@
+//! Dispatch from a `Layout` to the cheapest mimalloc entry point.
+//!
+//! Every mimalloc block is at least word aligned, so layouts that ask for no
+//! more than that can use the unaligned API, and small ones the `_small`
+//! family, skipping the alignment checks of the `_aligned` functions. The
+//! helpers are always inlined so that the branches fold away for layouts
+//! known at compile time, such as those of `Box<T>`.
+
+use core::alloc::Layout;
+use core::ffi::c_void;
+use core::mem;
+use ffi::*;
+
+/// The alignment of every block returned by mimalloc.
+const MIN_ALIGN: usize = mem::size_of::<usize>();
+
+/// Whether any mimalloc block of `size` bytes is aligned to `align`.
+///
+/// Like the system allocator, this also requires `align <= size`, since
+/// guarded allocations may be placed right before their guard page.
+#[inline(always)]
+pub(crate) fn is_natural(size: usize, align: usize) -> bool {
+    align <= MIN_ALIGN && align <= size
+}
+
+/// Whether `layout` can use the `_small` family.
+#[inline(always)]
+pub(crate) fn is_small(layout: Layout) -> bool {
+    is_natural(layout.size(), layout.align()) && layout.size() <= MI_SMALL_SIZE_MAX
+}
+
+#[inline(always)]
+pub(crate) unsafe fn malloc(layout: Layout) -> *mut c_void {
+    if is_small(layout) {
+        mi_malloc_small(layout.size())
+    } else if is_natural(layout.size(), layout.align()) {
+        mi_malloc(layout.size())
+    } else {
+        mi_malloc_aligned(layout.size(), layout.align())
+    }
+}
+
+#[inline(always)]
+pub(crate) unsafe fn zalloc(layout: Layout) -> *mut c_void {
+    if is_small(layout) {
+        mi_zalloc_small(layout.size())
+    } else if is_natural(layout.size(), layout.align()) {
+        mi_zalloc(layout.size())
+    } else {
+        mi_zalloc_aligned(layout.size(), layout.align())
+    }
+}
+
+/// Whether the block at `ptr` is aligned to `align` and already holds
+/// `new_size` bytes, so it can grow without moving.
+#[inline]
+pub(crate) unsafe fn grows_in_place(ptr: *mut c_void, new_size: usize, align: usize) -> bool {
+    if ptr as usize & (align - 1) != 0 {
+        return false;
+    }
+    // Unlike `mi_expand`, which always refuses when mimalloc pads blocks, the
+    // usable size accounts for the padding.
+    new_size <= mi_usable_size(ptr)
+}
+
+/// Re-allocate `ptr` of `old_size` bytes to `new_size` bytes aligned to
+/// `align`, without copying if the block is large enough already.
+#[inline(always)]
+pub(crate) unsafe fn realloc(
+    ptr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+    align: usize,
+) -> *mut c_void {
+    if new_size > old_size && !ptr.is_null() && grows_in_place(ptr, new_size, align) {
+        return ptr;
+    }
+    if is_natural(new_size, align) {
+        mi_realloc(ptr, new_size)
+    } else {
+        mi_realloc_aligned(ptr, new_size, align)
+    }
+}
+
+/// Free `ptr`, which was allocated with `layout`.
+///
+/// With the `sized_dealloc` feature the layout is passed on to mimalloc,
+/// which checks it when built with the `debug` feature. With the
+/// `check_dealloc` feature it is checked here in builds with debug
+/// assertions.
+#[inline(always)]
+pub(crate) unsafe fn free(ptr: *mut c_void, layout: Layout) {
+    #[cfg(feature = "check_dealloc")]
+    check_layout(ptr, layout);
+    #[cfg(feature = "sized_dealloc")]
+    mi_free_size_aligned(ptr, layout.size(), layout.align());
+    #[cfg(not(feature = "sized_dealloc"))]
+    {
+        let _ = layout;
+        mi_free(ptr);
+    }
+}
+
+/// Assert that `ptr` is null, or aligned to and usable for `layout`.
+#[cfg(feature = "check_dealloc")]
+#[inline]
+unsafe fn check_layout(ptr: *mut c_void, layout: Layout) {
+    if cfg!(debug_assertions) && !ptr.is_null() {
+        assert!(
+            ptr as usize & (layout.align() - 1) == 0,
+            "freeing {:p} with alignment {}, but it is not aligned to it",
+            ptr,
+            layout.align()
+        );
+        let usable = mi_usable_size(ptr);
+        assert!(
+            layout.size() <= usable,
+            "freeing {:p} with size {}, but only {} bytes are usable",
+            ptr,
+            layout.size(),
+            usable
+        );
+    }
+}
+
+#[cfg(feature = "extended")]
+#[inline(always)]
+pub(crate) unsafe fn heap_malloc(heap: *mut mi_heap_t, layout: Layout) -> *mut c_void {
+    if is_small(layout) {
+        mi_heap_malloc_small(heap, layout.size())
+    } else if is_natural(layout.size(), layout.align()) {
+        mi_heap_malloc(heap, layout.size())
+    } else {
+        mi_heap_malloc_aligned(heap, layout.size(), layout.align())
+    }
+}
+
+#[cfg(feature = "extended")]
+#[inline(always)]
+pub(crate) unsafe fn heap_zalloc(heap: *mut mi_heap_t, layout: Layout) -> *mut c_void {
+    if is_small(layout) {
+        mi_heap_zalloc_small(heap, layout.size())
+    } else if is_natural(layout.size(), layout.align()) {
+        mi_heap_zalloc(heap, layout.size())
+    } else {
+        mi_heap_zalloc_aligned(heap, layout.size(), layout.align())
+    }
+}
+
+#[cfg(feature = "extended")]
+#[inline(always)]
+pub(crate) unsafe fn heap_realloc(
+    heap: *mut mi_heap_t,
+    ptr: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+    align: usize,
+) -> *mut c_void {
+    if new_size > old_size && !ptr.is_null() && grows_in_place(ptr, new_size, align) {
+        return ptr;
+    }
+    if is_natural(new_size, align) {
+        mi_heap_realloc(heap, ptr, new_size)
+    } else {
+        mi_heap_realloc_aligned(heap, ptr, new_size, align)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_dispatches_on_the_layout() {
+        assert!(is_small(Layout::new::<u64>()));
+        assert!(is_small(Layout::new::<[usize; 4]>()));
+        assert!(!is_small(Layout::new::<u8>().align_to(16).unwrap()));
+        assert!(!is_small(Layout::from_size_align(0, 1).unwrap()));
+        assert!(!is_small(
+            Layout::array::<u8>(MI_SMALL_SIZE_MAX + 1).unwrap()
+        ));
+        assert!(is_natural(MI_SMALL_SIZE_MAX + 1, 1));
+        assert!(!is_natural(4, 8));
+    }
+
+    #[test]
+    fn it_aligns_every_path() {
+        let layouts = [
+            Layout::new::<u8>(),
+            Layout::new::<u64>(),
+            Layout::new::<u128>(),
+            Layout::from_size_align(24, 8).unwrap(),
+            Layout::from_size_align(40, 16).unwrap(),
+            Layout::from_size_align(4, 8).unwrap(),
+            Layout::from_size_align(MI_SMALL_SIZE_MAX, 8).unwrap(),
+            Layout::from_size_align(MI_SMALL_SIZE_MAX + 8, 8).unwrap(),
+            Layout::from_size_align(1 << 20, 4096).unwrap(),
+        ];
+        for layout in layouts {
+            unsafe {
+                let ptr = malloc(layout);
+                assert_eq!(ptr as usize % layout.align(), 0);
+                let new_size = layout.size() * 3;
+                let ptr = realloc(ptr, layout.size(), new_size, layout.align());
+                assert_eq!(ptr as usize % layout.align(), 0);
+                free(
+                    ptr,
+                    Layout::from_size_align(new_size, layout.align()).unwrap(),
+                );
+
+                let ptr = zalloc(layout) as *mut u8;
+                assert_eq!(ptr as usize % layout.align(), 0);
+                assert!((0..layout.size()).all(|i| *ptr.add(i) == 0));
+                free(ptr as *mut c_void, layout);
+            }
+        }
+    }
+
+    #[cfg(all(feature = "check_dealloc", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "bytes are usable")]
+    fn it_checks_the_freed_size() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let bigger = Layout::from_size_align(1 << 20, 8).unwrap();
+        unsafe { free(malloc(layout), bigger) };
+    }
+
+    #[cfg(all(feature = "check_dealloc", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "is not aligned")]
+    fn it_checks_the_freed_alignment() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let ptr = malloc(layout) as *mut u8;
+            free(ptr.add(8) as *mut c_void, layout.align_to(4096).unwrap());
+        }
+    }
+}
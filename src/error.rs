@@ -0,0 +1,398 @@
+use core::ffi::{c_int, c_void};
+use core::fmt;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+use ffi::*;
+
+/// The `errno` values mimalloc reports, which differ between targets.
+#[cfg(not(target_os = "wasi"))]
+pub(crate) mod errno {
+    use core::ffi::c_int;
+
+    pub use self::target::{EAGAIN, EOVERFLOW};
+    pub const EFAULT: c_int = 14;
+    pub const ENOMEM: c_int = 12;
+    pub const EINVAL: c_int = 22;
+
+    #[cfg(any(
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))]
+    mod target {
+        pub const EAGAIN: super::c_int = 35;
+        pub const EOVERFLOW: super::c_int = 84;
+    }
+
+    #[cfg(target_os = "openbsd")]
+    mod target {
+        pub const EAGAIN: super::c_int = 35;
+        pub const EOVERFLOW: super::c_int = 87;
+    }
+
+    // The MSVC and MinGW C runtimes.
+    #[cfg(windows)]
+    mod target {
+        pub const EAGAIN: super::c_int = 11;
+        pub const EOVERFLOW: super::c_int = 132;
+    }
+
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        any(target_arch = "mips", target_arch = "mips64")
+    ))]
+    mod target {
+        pub const EAGAIN: super::c_int = 11;
+        pub const EOVERFLOW: super::c_int = 79;
+    }
+
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        any(target_arch = "sparc", target_arch = "sparc64")
+    ))]
+    mod target {
+        pub const EAGAIN: super::c_int = 11;
+        pub const EOVERFLOW: super::c_int = 92;
+    }
+
+    // Linux and the other targets that follow its numbering.
+    #[cfg(not(any(
+        target_vendor = "apple",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly",
+        windows,
+        all(
+            any(target_os = "linux", target_os = "android"),
+            any(
+                target_arch = "mips",
+                target_arch = "mips64",
+                target_arch = "sparc",
+                target_arch = "sparc64"
+            )
+        )
+    )))]
+    mod target {
+        pub const EAGAIN: super::c_int = 11;
+        pub const EOVERFLOW: super::c_int = 75;
+    }
+}
+
+#[cfg(target_os = "wasi")]
+pub(crate) mod errno {
+    use core::ffi::c_int;
+
+    pub const EAGAIN: c_int = 6;
+    pub const EFAULT: c_int = 21;
+    pub const ENOMEM: c_int = 48;
+    pub const EINVAL: c_int = 28;
+    pub const EOVERFLOW: c_int = 61;
+}
+
+/// An error reported by mimalloc through `mi_register_error`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MiError {
+    /// A double free was detected (`EAGAIN`, only in debug and secure mode).
+    DoubleFree,
+    /// A corrupted free list or meta-data was detected (`EFAULT`, only in
+    /// debug and secure mode).
+    Corrupted,
+    /// Not enough memory was available to satisfy a request (`ENOMEM`).
+    OutOfMemory,
+    /// A request was too large, e.g. an overflowing `calloc` (`EOVERFLOW`).
+    Overflow,
+    /// An invalid pointer was freed or re-allocated (`EINVAL`).
+    InvalidPointer,
+    /// Any other error code.
+    Other(c_int),
+}
+
+impl MiError {
+    /// Convert an error code passed to an `mi_error_fun`.
+    pub fn from_code(code: c_int) -> Self {
+        match code {
+            errno::EAGAIN => MiError::DoubleFree,
+            errno::EFAULT => MiError::Corrupted,
+            errno::ENOMEM => MiError::OutOfMemory,
+            errno::EOVERFLOW => MiError::Overflow,
+            errno::EINVAL => MiError::InvalidPointer,
+            code => MiError::Other(code),
+        }
+    }
+
+    /// Return the error code used by mimalloc.
+    pub fn code(self) -> c_int {
+        match self {
+            MiError::DoubleFree => errno::EAGAIN,
+            MiError::Corrupted => errno::EFAULT,
+            MiError::OutOfMemory => errno::ENOMEM,
+            MiError::Overflow => errno::EOVERFLOW,
+            MiError::InvalidPointer => errno::EINVAL,
+            MiError::Other(code) => code,
+        }
+    }
+
+    fn counter(self) -> &'static AtomicUsize {
+        let index = match self {
+            MiError::DoubleFree => 0,
+            MiError::Corrupted => 1,
+            MiError::OutOfMemory => 2,
+            MiError::Overflow => 3,
+            MiError::InvalidPointer => 4,
+            MiError::Other(_) => 5,
+        };
+        &ERROR_COUNTS[index]
+    }
+}
+
+impl fmt::Display for MiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiError::DoubleFree => f.write_str("double free detected"),
+            MiError::Corrupted => f.write_str("corrupted heap meta-data detected"),
+            MiError::OutOfMemory => f.write_str("out of memory"),
+            MiError::Overflow => f.write_str("allocation size overflow"),
+            MiError::InvalidPointer => f.write_str("invalid pointer freed or re-allocated"),
+            MiError::Other(code) => write!(f, "mimalloc error {}", code),
+        }
+    }
+}
+
+/// What happens when mimalloc reports an error, see [`set_error_policy`].
+///
+/// Every error is counted regardless of the policy, see [`error_count`].
+/// [`MiError::Corrupted`] aborts under every policy, unless disabled with
+/// [`set_abort_on_corruption`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MiErrorPolicy {
+    /// Call the handler and continue. mimalloc itself already printed a
+    /// message to the registered output.
+    Log,
+    /// Call the handler, then abort the process.
+    Abort,
+    /// Call the handler and remember the error, so that the next call to
+    /// [`error_safe_point`] panics.
+    PanicAtSafePoint,
+    /// Only count the error, without calling the handler.
+    Count,
+}
+
+static HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+static POLICY: AtomicU8 = AtomicU8::new(MiErrorPolicy::Log as u8);
+static ABORT_ON_CORRUPTION: AtomicBool = AtomicBool::new(true);
+static PENDING: AtomicI32 = AtomicI32::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static ERROR_COUNTS: [AtomicUsize; 6] = [ZERO; 6];
+
+extern "C" {
+    fn abort() -> !;
+}
+
+/// Call `handler` whenever mimalloc reports an error.
+///
+/// The handler runs inside the failing mimalloc call, on any thread, so it
+/// should only record the error; it must not panic, as a panic aborts the
+/// process. What happens afterwards is decided by [`set_error_policy`],
+/// which defaults to [`MiErrorPolicy::Log`].
+///
+/// Registering a handler replaces mimalloc's default error handling, but
+/// [`MiError::Corrupted`] still aborts the process after the handler ran,
+/// see [`set_abort_on_corruption`].
+///
+/// ## Usage
+/// ```rust
+/// use mimalloc::{MiError, MiErrorPolicy};
+///
+/// fn on_error(err: MiError) {
+///     // Report `err` to monitoring.
+///     let _ = err;
+/// }
+///
+/// mimalloc::set_error_handler(on_error);
+/// mimalloc::set_error_policy(MiErrorPolicy::PanicAtSafePoint);
+/// ```
+pub fn set_error_handler(handler: fn(MiError)) {
+    HANDLER.store(handler as *mut (), Ordering::Release);
+    register();
+}
+
+/// Select what happens when mimalloc reports an error.
+///
+/// The policy does not apply to [`MiError::Corrupted`]: continuing on a heap
+/// with corrupted meta-data is unsafe, so it aborts the process after the
+/// handler ran, like mimalloc's default handler in secure mode. Use
+/// [`set_abort_on_corruption`] to opt out.
+pub fn set_error_policy(policy: MiErrorPolicy) {
+    POLICY.store(policy as u8, Ordering::Release);
+    register();
+}
+
+/// Select whether [`MiError::Corrupted`] aborts the process, regardless of
+/// the [`set_error_policy`] policy. Enabled by default.
+///
+/// Corruption is only detected in debug and secure mode. Disabling the abort
+/// lets the process continue on a heap that is known to be corrupted, which
+/// is only advisable to collect diagnostics, e.g. in tests.
+pub fn set_abort_on_corruption(enable: bool) {
+    ABORT_ON_CORRUPTION.store(enable, Ordering::Release);
+    register();
+}
+
+/// Return how many errors of the same kind as `error` were reported so far.
+///
+/// All [`MiError::Other`] codes share a single count. Errors are only counted
+/// once [`set_error_handler`] or [`set_error_policy`] has been called.
+pub fn error_count(error: MiError) -> usize {
+    error.counter().load(Ordering::Relaxed)
+}
+
+/// Take the first error remembered under [`MiErrorPolicy::PanicAtSafePoint`],
+/// if any.
+pub fn take_error() -> Option<MiError> {
+    match PENDING.swap(0, Ordering::AcqRel) {
+        0 => None,
+        code => Some(MiError::from_code(code)),
+    }
+}
+
+/// Panic if an error was remembered under [`MiErrorPolicy::PanicAtSafePoint`].
+///
+/// Call this at points where unwinding is safe, e.g. between requests.
+///
+/// # Panics
+/// Panics with the remembered error.
+pub fn error_safe_point() {
+    if let Some(error) = take_error() {
+        panic!("mimalloc reported an error: {}", error);
+    }
+}
+
+fn register() {
+    unsafe { mi_register_error(Some(on_error), ptr::null_mut()) }
+}
+
+unsafe extern "C" fn on_error(code: c_int, _arg: *mut c_void) {
+    let error = MiError::from_code(code);
+    error.counter().fetch_add(1, Ordering::Relaxed);
+
+    let policy = POLICY.load(Ordering::Acquire);
+    let handler = HANDLER.load(Ordering::Acquire);
+    if policy != MiErrorPolicy::Count as u8 && !handler.is_null() {
+        // Safety: only `fn(MiError)` pointers are stored in `HANDLER`.
+        let handler = mem::transmute::<*mut (), fn(MiError)>(handler);
+        handler(error);
+    }
+    let corrupted = error == MiError::Corrupted && ABORT_ON_CORRUPTION.load(Ordering::Acquire);
+    if corrupted || policy == MiErrorPolicy::Abort as u8 {
+        abort();
+    } else if policy == MiErrorPolicy::PanicAtSafePoint as u8 {
+        let _ = PENDING.compare_exchange(0, code, Ordering::AcqRel, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::process::{Command, Output};
+    use std::string::String;
+
+    static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+    fn on_error(_: MiError) {
+        HANDLED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn overflow() {
+        let ptr = unsafe { mi_malloc(usize::MAX - 4096) };
+        assert!(ptr.is_null());
+    }
+
+    #[test]
+    fn it_converts_error_codes() {
+        for code in 0..100 {
+            assert_eq!(MiError::from_code(code).code(), code);
+        }
+        assert_eq!(MiError::from_code(errno::EAGAIN), MiError::DoubleFree);
+        assert_eq!(MiError::from_code(errno::EFAULT), MiError::Corrupted);
+        assert_eq!(MiError::Overflow.code(), errno::EOVERFLOW);
+        assert_eq!(MiError::from_code(1), MiError::Other(1));
+    }
+
+    // Error handling is process-wide, so all policies are tested together,
+    // in a child process where no other test reports errors concurrently.
+    // A too large request reports both `Overflow` and `OutOfMemory`.
+    #[test]
+    #[ignore = "run in a child process"]
+    fn error_policies_child() {
+        set_error_policy(MiErrorPolicy::Count);
+        set_error_handler(on_error);
+        let count = error_count(MiError::Overflow);
+        overflow();
+        assert!(error_count(MiError::Overflow) > count);
+        assert_eq!(HANDLED.load(Ordering::Relaxed), 0);
+
+        set_error_policy(MiErrorPolicy::Log);
+        overflow();
+        let handled = HANDLED.load(Ordering::Relaxed);
+        assert!(handled > 0);
+        assert_eq!(take_error(), None);
+
+        set_error_policy(MiErrorPolicy::PanicAtSafePoint);
+        overflow();
+        assert!(HANDLED.load(Ordering::Relaxed) > handled);
+        assert_eq!(take_error(), Some(MiError::Overflow));
+        assert_eq!(take_error(), None);
+        error_safe_point();
+
+        set_error_policy(MiErrorPolicy::Log);
+    }
+
+    /// Mimic mimalloc detecting a corrupted free list, which aborts unless
+    /// `MIMALLOC_TEST_KEEP_GOING` is set.
+    #[test]
+    #[ignore = "run in a child process"]
+    fn corruption_child() {
+        set_error_policy(MiErrorPolicy::Log);
+        if std::env::var_os("MIMALLOC_TEST_KEEP_GOING").is_some() {
+            set_abort_on_corruption(false);
+        }
+        let count = error_count(MiError::Corrupted);
+        unsafe { super::on_error(errno::EFAULT, ptr::null_mut()) };
+        assert_eq!(error_count(MiError::Corrupted), count + 1);
+    }
+
+    fn run_child(name: &str, keep_going: bool) -> Output {
+        let mut child = Command::new(std::env::current_exe().unwrap());
+        child.args(["--ignored", "--exact", name, "--test-threads=1"]);
+        if keep_going {
+            child.env("MIMALLOC_TEST_KEEP_GOING", "1");
+        }
+        child.output().unwrap()
+    }
+
+    #[test]
+    fn it_applies_error_policies() {
+        let output = run_child("error::tests::error_policies_child", false);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+    }
+
+    #[test]
+    fn it_aborts_on_corruption() {
+        let output = run_child("error::tests::corruption_child", false);
+        assert!(!output.status.success(), "{:?}", output);
+        assert!(!String::from_utf8_lossy(&output.stdout).contains("test result"));
+
+        let output = run_child("error::tests::corruption_child", true);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::MiMalloc;
+use crate::{dispatch, MiMalloc};
+use core::alloc::Layout;
 use core::ffi::c_void;
 #[cfg(not(feature = "v2"))]
 use core::ffi::{c_char, CStr};
@@ -20,6 +21,22 @@ impl MiMalloc {
         ffi::mi_usable_size(ptr as *const c_void)
     }
 
+    /// Try to grow the memory block at `ptr` to `new_layout` without moving
+    /// it, for data structures whose buffers must stay put.
+    ///
+    /// Returns true if the block is aligned to `new_layout` and can already
+    /// hold `new_layout.size()` bytes, in which case it must be deallocated
+    /// with `new_layout` from then on. Returns false, leaving the block
+    /// untouched, if it would have to move.
+    ///
+    /// # Safety
+    /// `ptr` must point to a memory block allocated by mimalloc, or be null.
+    #[inline]
+    pub unsafe fn try_grow_in_place(&self, ptr: *mut u8, new_layout: Layout) -> bool {
+        !ptr.is_null()
+            && dispatch::grows_in_place(ptr as *mut c_void, new_layout.size(), new_layout.align())
+    }
+
     /// Extract a string containing the JSON statistics for the whole process
     ///
     /// Allocates (using mimalloc itself) to store the JSON structure.
@@ -51,7 +68,7 @@ impl core::ops::Deref for StatsJson {
     fn deref(&self) -> &Self::Target {
         unsafe {
             let cstr = CStr::from_ptr(self.inner.as_ptr());
-            &cstr
+            cstr
         }
     }
 }
@@ -88,6 +105,27 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_grows_in_place() {
+        unsafe {
+            let layout = Layout::from_size_align(100, 8).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.alloc(layout);
+            let usable = alloc.usable_size(ptr);
+            let fits = Layout::from_size_align(usable, 8).unwrap();
+            assert!(alloc.try_grow_in_place(ptr, fits));
+            ptr.write_bytes(0xAB, usable);
+
+            let too_big = Layout::from_size_align(usable + 1, 8).unwrap();
+            assert!(!alloc.try_grow_in_place(ptr, too_big));
+            let misaligned = Layout::from_size_align(usable, 1 << 20).unwrap();
+            assert!(!alloc.try_grow_in_place(ptr, misaligned));
+            assert!(!alloc.try_grow_in_place(core::ptr::null_mut(), fits));
+            alloc.dealloc(ptr, fits);
+        }
+    }
+
     #[test]
     #[cfg(not(feature = "v2"))]
     fn test_stats_json() {
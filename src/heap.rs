@@ -0,0 +1,197 @@
+use crate::dispatch;
+use core::alloc::Layout;
+use core::ffi::c_void;
+use core::ptr::NonNull;
+use ffi::*;
+
+/// An owned first-class mimalloc heap.
+///
+/// A fresh `mi_heap_t` is created on construction and deleted when the
+/// `MiHeap` is dropped. Blocks that are still alive at that point are migrated
+/// to the default heap, so they stay valid and can still be freed with
+/// [`MiHeap::dealloc`] or through [`MiMalloc`](crate::MiMalloc).
+///
+/// With mimalloc v2 a heap may only allocate on the thread that created it,
+/// so `MiHeap` is neither `Send` nor `Sync` when the `v2` feature is enabled.
+/// Heaps in v3 can allocate from any thread.
+///
+/// ## Usage
+/// ```rust
+/// use core::alloc::Layout;
+/// use mimalloc::MiHeap;
+///
+/// let heap = MiHeap::new();
+/// let layout = Layout::new::<u64>();
+/// let ptr = heap.alloc(layout);
+/// assert!(!ptr.is_null());
+/// unsafe { heap.dealloc(ptr, layout) };
+/// ```
+#[derive(Debug)]
+pub struct MiHeap {
+    heap: NonNull<mi_heap_t>,
+}
+
+#[cfg(not(feature = "v2"))]
+unsafe impl Send for MiHeap {}
+
+#[cfg(not(feature = "v2"))]
+unsafe impl Sync for MiHeap {}
+
+impl MiHeap {
+    /// Create a new heap.
+    ///
+    /// # Panics
+    /// Panics if mimalloc fails to create the heap, see [`MiHeap::try_new`]
+    /// for a non-panicking variant.
+    pub fn new() -> Self {
+        Self::try_new().expect("mimalloc failed to create a heap")
+    }
+
+    /// Create a new heap, or return `None` if mimalloc failed to create it.
+    pub fn try_new() -> Option<Self> {
+        Self::from_raw(unsafe { mi_heap_new() })
+    }
+
+    /// Take ownership of a freshly created heap, or return `None` if `heap`
+    /// is null.
+    pub(crate) fn from_raw(heap: *mut mi_heap_t) -> Option<Self> {
+        let heap = NonNull::new(heap)?;
+        Some(MiHeap { heap })
+    }
+
+    /// Return the underlying `mi_heap_t` for use with the raw FFI.
+    ///
+    /// The pointer stays owned by this `MiHeap` and must not be deleted or
+    /// destroyed by the caller.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut mi_heap_t {
+        self.heap.as_ptr()
+    }
+
+    /// Allocate memory described by `layout` out of this heap.
+    ///
+    /// Returns null if out of memory. Returns a unique pointer if `layout`
+    /// has a size of 0.
+    #[inline]
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { dispatch::heap_malloc(self.as_ptr(), layout) as *mut u8 }
+    }
+
+    /// Allocate zero-initialized memory described by `layout` out of this heap.
+    ///
+    /// Returns null if out of memory. Returns a unique pointer if `layout`
+    /// has a size of 0.
+    #[inline]
+    pub fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { dispatch::heap_zalloc(self.as_ptr(), layout) as *mut u8 }
+    }
+
+    /// Re-allocate memory to `new_size` bytes, keeping the alignment of
+    /// `layout`, in this heap.
+    ///
+    /// Returns null if out of memory, in which case `ptr` is not freed.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by mimalloc with `layout`, or be null.
+    #[inline]
+    pub unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        dispatch::heap_realloc(
+            self.as_ptr(),
+            ptr as *mut c_void,
+            layout.size(),
+            new_size,
+            layout.align(),
+        ) as *mut u8
+    }
+
+    /// Free memory previously allocated out of this heap.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by mimalloc with `layout`, or be null.
+    #[inline]
+    pub unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        dispatch::free(ptr as *mut c_void, layout);
+    }
+
+    /// Release outstanding resources in this heap.
+    ///
+    /// When `force` is true, all possible memory is released.
+    pub fn collect(&self, force: bool) {
+        unsafe { mi_heap_collect(self.as_ptr(), force) }
+    }
+}
+
+impl Default for MiHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MiHeap {
+    fn drop(&mut self) {
+        unsafe { mi_heap_delete(self.as_ptr()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_frees_allocated_memory() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(8, 8).unwrap();
+
+        let ptr = heap.alloc(layout);
+        assert!(!ptr.is_null());
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+
+    #[test]
+    fn it_frees_allocated_big_memory() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(1 << 20, 32).unwrap();
+
+        let ptr = heap.alloc(layout);
+        assert_eq!(ptr as usize % 32, 0);
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+
+    #[test]
+    fn it_zeroes_allocated_memory() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(256, 16).unwrap();
+
+        let ptr = heap.alloc_zeroed(layout);
+        let bytes = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+        assert!(bytes.iter().all(|&b| b == 0));
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+
+    #[test]
+    fn it_frees_reallocated_memory() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(8, 8).unwrap();
+
+        unsafe {
+            let ptr = heap.alloc(layout);
+            ptr.write(42);
+            let ptr = heap.realloc(ptr, layout, 1 << 20);
+            assert_eq!(ptr.read(), 42);
+            heap.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn it_keeps_blocks_alive_after_drop() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let heap = MiHeap::new();
+        let ptr = heap.alloc(layout);
+        drop(heap);
+
+        unsafe {
+            ptr.write_bytes(0xAB, layout.size());
+            mi_free(ptr as *mut c_void);
+        }
+    }
+}
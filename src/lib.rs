@@ -26,18 +26,94 @@
 //! [dependencies]
 //! mimalloc = { version = "*", features = ["secure"] }
 //! ```
+//!
+//! The `secure_1` to `secure_4` features select one of mimalloc's
+//! graduated levels instead, `secure` being level 4. See
+//! [`MiMalloc::secure_level`].
 
 extern crate libmimalloc_sys as ffi;
 
+#[cfg(feature = "arena")]
+mod arena;
+
+#[cfg(feature = "arena")]
+mod budget;
+
+#[cfg(feature = "extended")]
+mod aligned;
+
+#[cfg(feature = "extended")]
+mod array;
+
 #[cfg(feature = "extended")]
 mod extended;
 
+#[cfg(feature = "extended")]
+mod callback;
+
+#[cfg(feature = "extended")]
+mod capacity;
+
+#[cfg(feature = "extended")]
+mod deferred;
+
+mod dispatch;
+
+#[cfg(feature = "extended")]
+mod error;
+
+#[cfg(feature = "extended")]
+mod heap;
+
+#[cfg(feature = "extended")]
+pub mod options;
+
+#[cfg(feature = "extended")]
+mod output;
+
+#[cfg(feature = "extended")]
+mod scope;
+
+#[cfg(feature = "extended")]
+pub mod stats;
+
+#[cfg(all(feature = "extended", not(feature = "v2")))]
+mod theap;
+
+#[cfg(feature = "extended")]
+mod visit;
+
 #[cfg(feature = "nightly_allocator_api")]
 mod nightly_allocator_api;
 
 use core::alloc::{GlobalAlloc, Layout};
 use core::ffi::c_void;
-use ffi::*;
+
+#[cfg(feature = "arena")]
+pub use crate::arena::{MiArena, MiArenaError, MiRegionFlags};
+#[cfg(feature = "extended")]
+pub use crate::array::MiAllocError;
+#[cfg(feature = "arena")]
+pub use crate::budget::BudgetedHeap;
+#[cfg(feature = "extended")]
+pub use crate::capacity::GoodCapacity;
+#[cfg(feature = "extended")]
+pub use crate::deferred::{register_deferred_free, unregister_deferred_free, DeferredFree};
+#[cfg(feature = "extended")]
+pub use crate::error::{
+    error_count, error_safe_point, set_abort_on_corruption, set_error_handler, set_error_policy,
+    take_error, MiError, MiErrorPolicy,
+};
+#[cfg(feature = "extended")]
+pub use crate::heap::MiHeap;
+#[cfg(feature = "extended")]
+pub use crate::output::{reset_output, set_output, set_output_writer};
+#[cfg(feature = "extended")]
+pub use crate::scope::MiHeapScope;
+#[cfg(all(feature = "extended", not(feature = "v2")))]
+pub use crate::theap::MiThreadHeap;
+#[cfg(feature = "extended")]
+pub use crate::visit::HeapArea;
 
 /// Drop-in mimalloc global allocator.
 ///
@@ -50,25 +126,46 @@ use ffi::*;
 /// ```
 pub struct MiMalloc;
 
+impl MiMalloc {
+    /// Return the secure level mimalloc was built with, or `None` if it is
+    /// unknown because a system mimalloc is linked.
+    ///
+    /// Each level adds mitigations to the ones below it:
+    ///
+    /// 1. Guard pages around meta data, randomized arena addresses, and an
+    ///    abort on detected meta data corruption.
+    /// 2. Randomized allocation addresses within mimalloc pages.
+    /// 3. Encoded free lists, detecting corrupted free lists (buffer
+    ///    overflows) and invalid frees.
+    /// 4. Double free detection.
+    ///
+    /// Level 0 has no mitigations. The level is chosen with the `secure_1` to
+    /// `secure_4` features, where `secure` is level 4 and the highest enabled
+    /// level wins.
+    pub const fn secure_level() -> Option<u8> {
+        ffi::MI_SECURE_LEVEL
+    }
+}
+
 unsafe impl GlobalAlloc for MiMalloc {
     #[inline]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        mi_malloc_aligned(layout.size(), layout.align()) as *mut u8
+        dispatch::malloc(layout) as *mut u8
     }
 
     #[inline]
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        mi_zalloc_aligned(layout.size(), layout.align()) as *mut u8
+        dispatch::zalloc(layout) as *mut u8
     }
 
     #[inline]
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        mi_free(ptr as *mut c_void);
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        dispatch::free(ptr as *mut c_void, layout);
     }
 
     #[inline]
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        mi_realloc_aligned(ptr as *mut c_void, new_size, layout.align()) as *mut u8
+        dispatch::realloc(ptr as *mut c_void, layout.size(), new_size, layout.align()) as *mut u8
     }
 }
 
@@ -76,6 +173,25 @@ unsafe impl GlobalAlloc for MiMalloc {
 mod tests {
     use super::*;
 
+    #[test]
+    fn it_reports_the_secure_level() {
+        let level = if cfg!(feature = "secure_4") {
+            4
+        } else if cfg!(feature = "secure_3") {
+            3
+        } else if cfg!(feature = "secure_2") {
+            2
+        } else if cfg!(feature = "secure_1") {
+            1
+        } else {
+            0
+        };
+        // The level of a system mimalloc is unknown.
+        if let Some(built) = MiMalloc::secure_level() {
+            assert_eq!(built, level);
+        }
+    }
+
     #[test]
     fn it_frees_allocated_memory() {
         unsafe {
@@ -143,4 +259,25 @@ mod tests {
             alloc.dealloc(ptr, layout);
         }
     }
+
+    #[test]
+    fn it_reallocates_in_place_within_usable_size() {
+        unsafe {
+            let layout = Layout::from_size_align(100, 8).unwrap();
+            let alloc = MiMalloc;
+
+            let ptr = alloc.alloc(layout);
+            let usable = ffi::mi_usable_size(ptr as *const c_void);
+            assert!(usable >= layout.size());
+            assert_eq!(alloc.realloc(ptr, layout, usable), ptr);
+            alloc.dealloc(ptr, layout);
+
+            // Over-aligned blocks stay in place as long as they fit.
+            let aligned = Layout::from_size_align(64, 64).unwrap();
+            let ptr = alloc.alloc(aligned);
+            let usable = ffi::mi_usable_size(ptr as *const c_void);
+            assert_eq!(alloc.realloc(ptr, aligned, usable), ptr);
+            alloc.dealloc(ptr, aligned);
+        }
+    }
 }
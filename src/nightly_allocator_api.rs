@@ -1,27 +1,44 @@
-use crate::MiMalloc;
+#[cfg(feature = "arena")]
+use crate::BudgetedHeap;
+use crate::{dispatch, MiHeap, MiHeapScope, MiMalloc};
 use core::alloc::{AllocError, Allocator, Layout};
 use core::ffi::c_void;
 use core::ptr::{write_bytes, NonNull};
 use ffi::*;
 
-impl MiMalloc {
-    /// Interprets a raw pointer returned from a memory allocation call.
-    /// Tags the pointer with the associated allocation size,
-    /// or returns [`AllocError`] if the pointer was null.
-    ///
-    /// # Safety
-    ///
-    /// The `raw_ptr` must have been returned by mimalloc.
-    /// It should either refer to a live allocation or be null.
-    #[inline]
-    unsafe fn tag_allocation(raw_ptr: *mut c_void) -> Result<NonNull<[u8]>, AllocError> {
-        if let Some(ptr) = NonNull::new(raw_ptr as *mut _) {
-            // Safety: `raw_ptr` was previously allocated with mimalloc
-            let len = unsafe { mi_usable_size(raw_ptr) };
-            Ok(NonNull::from_raw_parts(ptr, len))
-        } else {
-            Err(AllocError)
-        }
+/// Interprets a raw pointer returned from a memory allocation call.
+/// Tags the pointer with the associated allocation size,
+/// or returns [`AllocError`] if the pointer was null.
+///
+/// # Safety
+///
+/// The `raw_ptr` must have been returned by mimalloc.
+/// It should either refer to a live allocation or be null.
+#[inline]
+unsafe fn tag_allocation(raw_ptr: *mut c_void) -> Result<NonNull<[u8]>, AllocError> {
+    if let Some(ptr) = NonNull::new(raw_ptr as *mut _) {
+        // Safety: `raw_ptr` was previously allocated with mimalloc
+        let len = unsafe { mi_usable_size(raw_ptr) };
+        Ok(NonNull::from_raw_parts(ptr, len))
+    } else {
+        Err(AllocError)
+    }
+}
+
+/// Zeroes the bytes of `result` past `old_usable_size`.
+///
+/// # Safety
+///
+/// `result` must refer to a live allocation of at least `old_usable_size` bytes.
+#[inline]
+unsafe fn zero_tail(result: NonNull<[u8]>, old_usable_size: usize) {
+    // Safety: only bytes within the bounds of the new allocation are written
+    unsafe {
+        write_bytes(
+            result.cast::<u8>().add(old_usable_size).as_ptr(),
+            0,
+            result.len() - old_usable_size,
+        );
     }
 }
 
@@ -31,7 +48,7 @@ unsafe impl Allocator for MiMalloc {
         match layout.size() {
             0 => Ok(NonNull::slice_from_raw_parts(layout.dangling_ptr(), 0)),
             // Safety: the pointer passed to `tag_allocation` is either null or comes from mimalloc
-            _ => unsafe { Self::tag_allocation(mi_malloc_aligned(layout.size(), layout.align())) },
+            _ => unsafe { tag_allocation(dispatch::malloc(layout)) },
         }
     }
 
@@ -41,7 +58,7 @@ unsafe impl Allocator for MiMalloc {
             // Do nothing
             0 => {}
             // Safety: by the function preconditions, `ptr` came from this allocator
-            _ => unsafe { mi_free(ptr.as_ptr() as *mut _) },
+            _ => unsafe { dispatch::free(ptr.as_ptr() as *mut _, layout) },
         }
     }
 
@@ -50,7 +67,7 @@ unsafe impl Allocator for MiMalloc {
         match layout.size() {
             0 => Ok(NonNull::slice_from_raw_parts(layout.dangling_ptr(), 0)),
             // Safety: the pointer passed to `tag_allocation` is either null or comes from mimalloc
-            _ => unsafe { Self::tag_allocation(mi_zalloc_aligned(layout.size(), layout.align())) },
+            _ => unsafe { tag_allocation(dispatch::zalloc(layout)) },
         }
     }
 
@@ -68,8 +85,9 @@ unsafe impl Allocator for MiMalloc {
             (0, _) => self.allocate(new_layout),
             // Safety: by the function preconditions, `ptr` came from this allocator
             (_, _) => unsafe {
-                Self::tag_allocation(mi_realloc_aligned(
+                tag_allocation(dispatch::realloc(
                     ptr.as_ptr() as *mut _,
+                    old_layout.size(),
                     new_layout.size(),
                     new_layout.align(),
                 ))
@@ -93,14 +111,116 @@ unsafe impl Allocator for MiMalloc {
         // Safety: by the function preconditions, `ptr` came from this allocator
         let result = unsafe { self.grow(ptr, old_layout, new_layout)? };
 
-        // Safety: only bytes within the bounds of the new allocation are written
-        unsafe {
-            write_bytes(
-                result.cast::<u8>().add(old_usable_size).as_ptr(),
-                0,
-                result.len() - old_usable_size,
-            );
+        // Safety: `result` is at least as large as the old allocation
+        unsafe { zero_tail(result, old_usable_size) };
+
+        Ok(result)
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match (old_layout.size(), new_layout.size()) {
+            // Do nothing
+            (0, 0) => Ok(NonNull::slice_from_raw_parts(new_layout.dangling_ptr(), 0)),
+            // Safety: by the function preconditions, `ptr` came from this allocator
+            (_, 0) => unsafe {
+                self.deallocate(ptr, old_layout);
+                Ok(NonNull::slice_from_raw_parts(new_layout.dangling_ptr(), 0))
+            },
+            // Safety: by the function preconditions, `ptr` came from this allocator
+            (_, _) => unsafe {
+                tag_allocation(dispatch::realloc(
+                    ptr.as_ptr() as *mut _,
+                    old_layout.size(),
+                    new_layout.size(),
+                    new_layout.align(),
+                ))
+            },
+        }
+    }
+}
+
+/// Allocates out of a specific [`MiHeap`].
+///
+/// Together with the blanket `Allocator` impl for references, this lets
+/// collections borrow a heap, e.g. `Vec::new_in(&heap)` or
+/// `Box::new_in(x, &heap)`.
+unsafe impl Allocator for MiHeap {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match layout.size() {
+            0 => Ok(NonNull::slice_from_raw_parts(layout.dangling_ptr(), 0)),
+            // Safety: the pointer passed to `tag_allocation` is either null or comes from mimalloc
+            _ => unsafe { tag_allocation(dispatch::heap_malloc(self.as_ptr(), layout)) },
         }
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match layout.size() {
+            // Do nothing
+            0 => {}
+            // Safety: by the function preconditions, `ptr` came from this allocator
+            _ => unsafe { dispatch::free(ptr.as_ptr() as *mut _, layout) },
+        }
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match layout.size() {
+            0 => Ok(NonNull::slice_from_raw_parts(layout.dangling_ptr(), 0)),
+            // Safety: the pointer passed to `tag_allocation` is either null or comes from mimalloc
+            _ => unsafe { tag_allocation(dispatch::heap_zalloc(self.as_ptr(), layout)) },
+        }
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match (old_layout.size(), new_layout.size()) {
+            // Do nothing
+            (0, 0) => Ok(NonNull::slice_from_raw_parts(new_layout.dangling_ptr(), 0)),
+            (0, _) => self.allocate(new_layout),
+            // Safety: by the function preconditions, `ptr` came from this allocator
+            (_, _) => unsafe {
+                tag_allocation(dispatch::heap_realloc(
+                    self.as_ptr(),
+                    ptr.as_ptr() as *mut _,
+                    old_layout.size(),
+                    new_layout.size(),
+                    new_layout.align(),
+                ))
+            },
+        }
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_usable_size = match old_layout.size() {
+            0 => 0,
+            // Safety: `ptr` refers to a valid allocation from mimalloc
+            _ => unsafe { mi_usable_size(ptr.as_ptr() as *mut _) },
+        };
+
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        let result = unsafe { self.grow(ptr, old_layout, new_layout)? };
+
+        // Safety: `result` is at least as large as the old allocation
+        unsafe { zero_tail(result, old_usable_size) };
 
         Ok(result)
     }
@@ -122,8 +242,10 @@ unsafe impl Allocator for MiMalloc {
             },
             // Safety: by the function preconditions, `ptr` came from this allocator
             (_, _) => unsafe {
-                Self::tag_allocation(mi_realloc_aligned(
+                tag_allocation(dispatch::heap_realloc(
+                    self.as_ptr(),
                     ptr.as_ptr() as *mut _,
+                    old_layout.size(),
                     new_layout.size(),
                     new_layout.align(),
                 ))
@@ -132,6 +254,115 @@ unsafe impl Allocator for MiMalloc {
     }
 }
 
+/// Allocates out of a [`MiHeapScope`], e.g. `Vec::new_in(scope)`.
+///
+/// Blocks that are still allocated when the scope ends are released along with
+/// the rest of the scope.
+unsafe impl Allocator for MiHeapScope {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap().allocate(layout)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        unsafe { self.heap.deallocate(ptr, layout) }
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap().allocate_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        unsafe { self.heap().grow(ptr, old_layout, new_layout) }
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        unsafe { self.heap().grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        unsafe { self.heap().shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+/// Allocates out of a [`BudgetedHeap`], failing with [`AllocError`] once its
+/// budget is exhausted.
+#[cfg(feature = "arena")]
+unsafe impl Allocator for BudgetedHeap {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap.allocate(layout)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        unsafe { self.heap.deallocate(ptr, layout) }
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap.allocate_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        unsafe { self.heap.grow(ptr, old_layout, new_layout) }
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        unsafe { self.heap.grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Safety: by the function preconditions, `ptr` came from this allocator
+        unsafe { self.heap.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -173,6 +404,16 @@ mod tests {
         unsafe { MiMalloc.deallocate(ptr.cast(), new_layout) };
     }
 
+    #[test]
+    fn it_grows_in_place_within_usable_size() {
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let block = MiMalloc.allocate(layout).unwrap();
+        let new_layout = Layout::from_size_align(block.len(), 8).unwrap();
+        let grown = unsafe { MiMalloc.grow(block.cast(), layout, new_layout).unwrap() };
+        assert_eq!(grown.cast::<u8>(), block.cast::<u8>());
+        unsafe { MiMalloc.deallocate(grown.cast(), new_layout) };
+    }
+
     #[test]
     fn it_frees_grown_big_memory() {
         let layout = Layout::from_size_align(1 << 20, 32).unwrap();
@@ -199,4 +440,82 @@ mod tests {
         assert_eq!(ptr.len(), 0);
         unsafe { MiMalloc.deallocate(ptr.cast(), empty_layout) };
     }
+
+    #[test]
+    fn heap_frees_allocated_memory() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        let ptr = heap.allocate(layout).unwrap();
+        unsafe { heap.deallocate(ptr.cast(), layout) };
+    }
+
+    #[test]
+    fn heap_frees_grown_big_memory() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(1 << 20, 32).unwrap();
+        let new_layout = Layout::from_size_align(2 << 20, 32).unwrap();
+        let ptr = heap.allocate(layout).unwrap();
+        let ptr = unsafe { heap.grow(ptr.cast(), layout, new_layout).unwrap() };
+        unsafe { heap.deallocate(ptr.cast(), new_layout) };
+    }
+
+    #[test]
+    fn heap_zeroes_grown_memory() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        let new_layout = Layout::from_size_align(4096, 8).unwrap();
+        let ptr = heap.allocate(layout).unwrap();
+        unsafe { ptr.cast::<u8>().write_bytes(0xFF, layout.size()) };
+        let ptr = unsafe { heap.grow_zeroed(ptr.cast(), layout, new_layout).unwrap() };
+        let bytes = unsafe { ptr.as_ref() };
+        assert!(bytes[8..].iter().all(|&b| b == 0));
+        unsafe { heap.deallocate(ptr.cast(), new_layout) };
+    }
+
+    #[test]
+    fn heap_backs_collections() {
+        extern crate alloc;
+        use alloc::{boxed::Box, vec::Vec};
+
+        let heap = MiHeap::new();
+        let mut v = Vec::new_in(&heap);
+        v.extend(0..1024u32);
+        let b = Box::new_in(7u64, &heap);
+        assert_eq!(v.iter().sum::<u32>(), 523776);
+        assert_eq!(*b, 7);
+    }
+
+    #[test]
+    fn scope_backs_collections() {
+        extern crate alloc;
+        use alloc::vec::Vec;
+
+        let sum = MiHeap::scope(|scope| {
+            let mut v: Vec<u32, &MiHeapScope> = Vec::new_in(scope);
+            v.extend(0..1024u32);
+            let leaked = v.leak();
+            leaked.iter().sum::<u32>()
+        });
+        assert_eq!(sum, 523776);
+    }
+
+    #[cfg(feature = "arena")]
+    #[test]
+    fn budgeted_heap_reports_alloc_error() {
+        extern crate alloc;
+        use alloc::vec::Vec;
+
+        let heap = BudgetedHeap::new(32 << 20).unwrap();
+        let mut v: Vec<u8, &BudgetedHeap> = Vec::new_in(&heap);
+        v.extend_from_slice(&[1; 1024]);
+        assert!(v.try_reserve(heap.capacity() + 1).is_err());
+
+        let layout = Layout::from_size_align(1 << 20, 8).unwrap();
+        let mut allocated = 0;
+        while let Ok(block) = heap.allocate(layout) {
+            allocated += block.len();
+        }
+        assert!(allocated <= heap.capacity());
+        assert_eq!(heap.allocate(layout), Err(AllocError));
+    }
 }
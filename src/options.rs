@@ -0,0 +1,565 @@
+//! Typed access to mimalloc's runtime options.
+//!
+//! Every option of the selected mimalloc version (v3 by default, v2 with the
+//! `v2` feature) is available as a [`MiOption`]. Deprecated options that no
+//! longer have any effect are left out.
+//!
+//! ## Usage
+//! ```rust
+//! use mimalloc::options::MiOption;
+//!
+//! MiOption::PurgeDelay.set(100);
+//! assert_eq!(MiOption::PurgeDelay.get(), 100);
+//!
+//! // Dump the effective configuration.
+//! for option in MiOption::all() {
+//!     println!("{option} = {}", option.value());
+//! }
+//! ```
+//!
+//! Options are best set at startup, before other threads allocate, as
+//! mimalloc reads them without synchronization.
+
+use core::convert::TryFrom;
+use core::ffi::c_long;
+use core::fmt;
+use core::ops::RangeInclusive;
+use ffi::*;
+
+/// How the value of a [`MiOption`] is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MiOptionKind {
+    /// An on/off flag.
+    Bool,
+    /// A plain (possibly negative) number, like a count or a delay.
+    Number,
+    /// A size in bytes.
+    Size,
+}
+
+/// The typed value of a [`MiOption`], see [`MiOption::value`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MiOptionValue {
+    /// Value of a [`MiOptionKind::Bool`] option.
+    Bool(bool),
+    /// Value of a [`MiOptionKind::Number`] option.
+    Number(c_long),
+    /// Value of a [`MiOptionKind::Size`] option, in bytes.
+    Size(usize),
+}
+
+impl fmt::Display for MiOptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiOptionValue::Bool(value) => value.fmt(f),
+            MiOptionValue::Number(value) => value.fmt(f),
+            MiOptionValue::Size(value) => value.fmt(f),
+        }
+    }
+}
+
+macro_rules! options {
+    ($(
+        $(#[doc = $doc:literal])*
+        $(#[cfg($cfg:meta)])?
+        $variant:ident($raw:ident, $name:literal, $kind:ident),
+    )*) => {
+        /// A mimalloc runtime option.
+        ///
+        /// See the [module documentation](self) for an example.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[non_exhaustive]
+        pub enum MiOption {
+            $(
+                $(#[doc = $doc])*
+                $(#[cfg($cfg)])?
+                $variant,
+            )*
+        }
+
+        impl MiOption {
+            /// Look up the option with the given raw `mi_option_t` value.
+            ///
+            /// Returns `None` for out of range and deprecated options.
+            #[allow(non_upper_case_globals)]
+            pub fn from_raw(raw: mi_option_t) -> Option<Self> {
+                match raw {
+                    $(
+                        $(#[cfg($cfg)])?
+                        $raw => Some(MiOption::$variant),
+                    )*
+                    _ => None,
+                }
+            }
+
+            /// Return the raw `mi_option_t` value of this option.
+            pub fn as_raw(self) -> mi_option_t {
+                match self {
+                    $(
+                        $(#[cfg($cfg)])?
+                        MiOption::$variant => $raw,
+                    )*
+                }
+            }
+
+            /// Return the name of this option.
+            ///
+            /// This is the name used by mimalloc in its output, and also
+            /// the suffix of the `MIMALLOC_` environment variable that
+            /// configures it (in upper case).
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(
+                        $(#[cfg($cfg)])?
+                        MiOption::$variant => $name,
+                    )*
+                }
+            }
+
+            /// Return how the value of this option is interpreted.
+            pub fn kind(self) -> MiOptionKind {
+                match self {
+                    $(
+                        $(#[cfg($cfg)])?
+                        MiOption::$variant => MiOptionKind::$kind,
+                    )*
+                }
+            }
+        }
+    };
+}
+
+options! {
+    /// Print error messages.
+    ShowErrors(mi_option_show_errors, "show_errors", Bool),
+    /// Print statistics on termination.
+    ShowStats(mi_option_show_stats, "show_stats", Bool),
+    /// Print verbose messages.
+    Verbose(mi_option_verbose, "verbose", Number),
+    /// Eagerly commit segments (after `eager_commit_delay` segments).
+    #[cfg(feature = "v2")]
+    EagerCommit(mi_option_eager_commit, "eager_commit", Bool),
+    /// Eagerly commit arenas. Use 2 to enable just on overcommit systems.
+    ArenaEagerCommit(mi_option_arena_eager_commit, "arena_eager_commit", Number),
+    /// Decommit memory on a purge, instead of resetting it.
+    PurgeDecommits(mi_option_purge_decommits, "purge_decommits", Bool),
+    /// Allow use of large (2 or 4 MiB) OS pages, implies eager commit.
+    AllowLargeOsPages(mi_option_allow_large_os_pages, "allow_large_os_pages", Bool),
+    /// Reserve N huge OS pages (1GiB pages) at startup.
+    ReserveHugeOsPages(mi_option_reserve_huge_os_pages, "reserve_huge_os_pages", Number),
+    /// Reserve huge OS pages at a specific NUMA node.
+    ReserveHugeOsPagesAt(mi_option_reserve_huge_os_pages_at, "reserve_huge_os_pages_at", Number),
+    /// Reserve the given amount of OS memory in an arena at startup.
+    ReserveOsMemory(mi_option_reserve_os_memory, "reserve_os_memory", Size),
+    /// Immediately purge delayed purges on thread termination.
+    #[cfg(feature = "v2")]
+    AbandonedPagePurge(mi_option_abandoned_page_purge, "abandoned_page_purge", Bool),
+    /// The first N segments per thread are not eagerly committed.
+    #[cfg(feature = "v2")]
+    EagerCommitDelay(mi_option_eager_commit_delay, "eager_commit_delay", Number),
+    /// Delay memory purging by N milliseconds; 0 purges immediately and -1
+    /// never purges.
+    PurgeDelay(mi_option_purge_delay, "purge_delay", Number),
+    /// Use at most N NUMA nodes, or all available ones with 0.
+    UseNumaNodes(mi_option_use_numa_nodes, "use_numa_nodes", Number),
+    /// Do not use OS memory for allocation, only programmatically reserved
+    /// arenas.
+    DisallowOsAlloc(mi_option_disallow_os_alloc, "disallow_os_alloc", Bool),
+    /// Tag used for OS logging (macOS only for now).
+    OsTag(mi_option_os_tag, "os_tag", Number),
+    /// Issue at most N error messages.
+    MaxErrors(mi_option_max_errors, "max_errors", Number),
+    /// Issue at most N warning messages.
+    MaxWarnings(mi_option_max_warnings, "max_warnings", Number),
+    /// Max. percentage of the abandoned segments that can be reclaimed per
+    /// try.
+    #[cfg(feature = "v2")]
+    MaxSegmentReclaim(mi_option_max_segment_reclaim, "max_segment_reclaim", Number),
+    /// Release all memory on exit; sometimes used for dynamic unloading but
+    /// can be unsafe.
+    DestroyOnExit(mi_option_destroy_on_exit, "destroy_on_exit", Bool),
+    /// Initial memory size for arena reservation.
+    ArenaReserve(mi_option_arena_reserve, "arena_reserve", Size),
+    /// Multiplier for `purge_delay` for the purging delay of arenas.
+    ArenaPurgeMult(mi_option_arena_purge_mult, "arena_purge_mult", Number),
+    /// Extend the purge delay on each subsequent delay.
+    #[cfg(feature = "v2")]
+    PurgeExtendDelay(mi_option_purge_extend_delay, "purge_extend_delay", Number),
+    /// Allow to reclaim an abandoned segment on a free.
+    #[cfg(feature = "v2")]
+    AbandonedReclaimOnFree(mi_option_abandoned_reclaim_on_free, "abandoned_reclaim_on_free", Bool),
+    /// Do not use arenas for allocation, except when using specific arena ids.
+    DisallowArenaAlloc(mi_option_disallow_arena_alloc, "disallow_arena_alloc", Bool),
+    /// Retry on out-of-memory for N milliseconds, 0 disables retries (only on
+    /// Windows).
+    RetryOnOom(mi_option_retry_on_oom, "retry_on_oom", Number),
+    /// Allow visiting heap blocks from abandoned threads.
+    VisitAbandoned(mi_option_visit_abandoned, "visit_abandoned", Bool),
+    /// Minimal rounded object size for guarded objects.
+    GuardedMin(mi_option_guarded_min, "guarded_min", Size),
+    /// Maximal rounded object size for guarded objects.
+    GuardedMax(mi_option_guarded_max, "guarded_max", Size),
+    /// Disregard the minimal alignment requirement to always place guarded
+    /// blocks exactly in front of a guard page.
+    GuardedPrecise(mi_option_guarded_precise, "guarded_precise", Bool),
+    /// Guard 1 out of N allocations in the min/max range.
+    GuardedSampleRate(mi_option_guarded_sample_rate, "guarded_sample_rate", Number),
+    /// Seed for guarded sampling, for a (more) deterministic re-execution
+    /// when a guard page is triggered.
+    GuardedSampleSeed(mi_option_guarded_sample_seed, "guarded_sample_seed", Number),
+    /// Target number of segments per thread (experimental).
+    #[cfg(feature = "v2")]
+    TargetSegmentsPerThread(mi_option_target_segments_per_thread, "target_segments_per_thread", Number),
+    /// Collect heaps every N generic allocation calls.
+    GenericCollect(mi_option_generic_collect, "generic_collect", Number),
+    /// Reclaim abandoned pages on a free: -1 never, 0 only into the
+    /// originating heap, 1 always.
+    #[cfg(not(feature = "v2"))]
+    PageReclaimOnFree(mi_option_page_reclaim_on_free, "page_reclaim_on_free", Number),
+    /// Retain N full (small) pages per size class.
+    #[cfg(not(feature = "v2"))]
+    PageFullRetain(mi_option_page_full_retain, "page_full_retain", Number),
+    /// Max candidate pages to consider for allocation.
+    #[cfg(not(feature = "v2"))]
+    PageMaxCandidates(mi_option_page_max_candidates, "page_max_candidates", Number),
+    /// Max user space virtual address bits to consider.
+    #[cfg(not(feature = "v2"))]
+    MaxVabits(mi_option_max_vabits, "max_vabits", Number),
+    /// Commit the full pagemap, to always catch invalid pointer uses.
+    #[cfg(not(feature = "v2"))]
+    PagemapCommit(mi_option_pagemap_commit, "pagemap_commit", Bool),
+    /// Commit page memory on demand.
+    #[cfg(not(feature = "v2"))]
+    PageCommitOnDemand(mi_option_page_commit_on_demand, "page_commit_on_demand", Number),
+    /// Don't reclaim pages of the same originating heap if we already own N
+    /// pages in that size class.
+    #[cfg(not(feature = "v2"))]
+    PageMaxReclaim(mi_option_page_max_reclaim, "page_max_reclaim", Number),
+    /// Don't reclaim pages across threads if we already own N pages in that
+    /// size class.
+    #[cfg(not(feature = "v2"))]
+    PageCrossThreadMaxReclaim(mi_option_page_cross_thread_max_reclaim, "page_cross_thread_max_reclaim", Number),
+    /// Allow transparent huge pages.
+    AllowThp(mi_option_allow_thp, "allow_thp", Bool),
+    /// Minimal purge size; 0 resolves to either 64 KiB or 2 MiB with THP.
+    #[cfg(not(feature = "v2"))]
+    MinimalPurgeSize(mi_option_minimal_purge_size, "minimal_purge_size", Size),
+    /// Maximal object size that can be allocated in an arena.
+    #[cfg(not(feature = "v2"))]
+    ArenaMaxObjectSize(mi_option_arena_max_object_size, "arena_max_object_size", Size),
+    /// Associate the local NUMA node with an initial arena allocation.
+    #[cfg(not(feature = "v2"))]
+    ArenaIsNumaLocal(mi_option_arena_is_numa_local, "arena_is_numa_local", Bool),
+}
+
+impl MiOption {
+    /// Iterate over every option of the selected mimalloc version.
+    pub fn all() -> impl Iterator<Item = MiOption> {
+        (0.._mi_option_last).filter_map(MiOption::from_raw)
+    }
+
+    /// Returns true if this option is enabled.
+    pub fn is_enabled(self) -> bool {
+        unsafe { mi_option_is_enabled(self.as_raw()) }
+    }
+
+    /// Enable or disable this option.
+    pub fn set_enabled(self, enable: bool) {
+        unsafe { mi_option_set_enabled(self.as_raw(), enable) }
+    }
+
+    /// Enable or disable this option, unless it was already set explicitly.
+    pub fn set_enabled_default(self, enable: bool) {
+        unsafe { mi_option_set_enabled_default(self.as_raw(), enable) }
+    }
+
+    /// Returns the raw value of this option.
+    ///
+    /// Sizes that mimalloc stores in KiB are returned as is, use
+    /// [`MiOption::get_size`] to get them in bytes.
+    pub fn get(self) -> c_long {
+        unsafe { mi_option_get(self.as_raw()) }
+    }
+
+    /// Set the raw value of this option.
+    pub fn set(self, value: c_long) {
+        unsafe { mi_option_set(self.as_raw(), value) }
+    }
+
+    /// Set the raw value of this option, unless it was already set explicitly.
+    pub fn set_default(self, value: c_long) {
+        unsafe { mi_option_set_default(self.as_raw(), value) }
+    }
+
+    /// Returns the value of this option as a `usize`, with negative values
+    /// returned as 0.
+    pub fn get_usize(self) -> usize {
+        let value = unsafe { mi_option_get_clamp(self.as_raw(), 0, c_long::MAX) };
+        value as usize
+    }
+
+    /// Set the value of this option from a `usize`, saturating at the
+    /// largest value a C `long` can hold.
+    pub fn set_usize(self, value: usize) {
+        self.set(c_long::try_from(value).unwrap_or(c_long::MAX))
+    }
+
+    /// Returns the value of this option as a size in bytes.
+    ///
+    /// Takes care of options that mimalloc stores in KiB, and returns
+    /// negative values as 0.
+    pub fn get_size(self) -> usize {
+        unsafe { mi_option_get_size(self.as_raw()) }
+    }
+
+    /// Set the value of this option from a size in bytes.
+    ///
+    /// For options that mimalloc stores in KiB, the size is rounded up to the
+    /// next KiB.
+    pub fn set_size(self, bytes: usize) {
+        if self.is_stored_in_kib() {
+            self.set_usize(bytes.div_ceil(1024))
+        } else {
+            self.set_usize(bytes)
+        }
+    }
+
+    /// Returns the current value of this option, interpreted according to
+    /// its [`kind`](MiOption::kind).
+    pub fn value(self) -> MiOptionValue {
+        match self.kind() {
+            MiOptionKind::Bool => MiOptionValue::Bool(self.is_enabled()),
+            MiOptionKind::Number => MiOptionValue::Number(self.get()),
+            MiOptionKind::Size => MiOptionValue::Size(self.get_size()),
+        }
+    }
+
+    fn is_stored_in_kib(self) -> bool {
+        match self {
+            MiOption::ReserveOsMemory | MiOption::ArenaReserve => true,
+            #[cfg(not(feature = "v2"))]
+            MiOption::MinimalPurgeSize | MiOption::ArenaMaxObjectSize => true,
+            _ => false,
+        }
+    }
+}
+
+/// Guard 1 out of `rate` allocations in the guarded size window with a guard
+/// page right behind the object, so that writing past its end faults.
+///
+/// A `rate` of 0 disables guarded objects and 1 guards every object. A `seed`
+/// of 0 picks a random start point; a fixed seed makes a faulting run easier
+/// to reproduce.
+///
+/// Sets [`MiOption::GuardedSampleRate`] and [`MiOption::GuardedSampleSeed`],
+/// which mimalloc reads when it creates a heap, and applies them to the
+/// default heap of the calling thread right away. Heaps of other threads that
+/// already allocated keep their rate. Only has an effect with the `guarded`
+/// feature, which defaults to a rate of 4000 in release builds.
+///
+/// ## Usage
+/// ```rust
+/// use mimalloc::options::{self, MiOption};
+///
+/// options::set_guarded_sample_rate(100, 42);
+/// assert_eq!(MiOption::GuardedSampleRate.get(), 100);
+/// # options::set_guarded_sample_rate(0, 0);
+/// ```
+pub fn set_guarded_sample_rate(rate: usize, seed: usize) {
+    MiOption::GuardedSampleRate.set_usize(rate);
+    MiOption::GuardedSampleSeed.set_usize(seed);
+    unsafe {
+        #[cfg(feature = "v2")]
+        mi_heap_guarded_set_sample_rate(mi_heap_get_default(), rate, seed);
+        #[cfg(not(feature = "v2"))]
+        mi_theap_guarded_set_sample_rate(mi_theap_get_default(), rate, seed);
+    }
+}
+
+/// Only guard allocations with a size in `window`, in bytes.
+///
+/// Sets [`MiOption::GuardedMin`] and [`MiOption::GuardedMax`], and applies
+/// them to the default heap of the calling thread right away, like
+/// [`set_guarded_sample_rate`]. An empty window guards objects of exactly
+/// its start size.
+pub fn set_guarded_size_window(window: RangeInclusive<usize>) {
+    let (min, max) = window.into_inner();
+    let max = max.max(min);
+    MiOption::GuardedMin.set_size(min);
+    MiOption::GuardedMax.set_size(max);
+    unsafe {
+        #[cfg(feature = "v2")]
+        mi_heap_guarded_set_size_bound(mi_heap_get_default(), min, max);
+        #[cfg(not(feature = "v2"))]
+        mi_theap_guarded_set_size_bound(mi_theap_get_default(), min, max);
+    }
+}
+
+impl fmt::Display for MiOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_raw_values() {
+        for option in MiOption::all() {
+            assert_eq!(MiOption::from_raw(option.as_raw()), Some(option));
+        }
+        assert_eq!(MiOption::from_raw(-1), None);
+        assert_eq!(MiOption::from_raw(_mi_option_last), None);
+    }
+
+    /// The `mi_option_deprecated_*` slots of `mi_option_t`, which `MiOption`
+    /// leaves out.
+    #[cfg(feature = "v2")]
+    const DEPRECATED: &[(mi_option_t, &str)] = &[
+        (10, "segment_cache"),
+        (11, "page_reset"),
+        (13, "segment_reset"),
+    ];
+    #[cfg(not(feature = "v2"))]
+    const DEPRECATED: &[(mi_option_t, &str)] = &[
+        (3, "eager_commit"),
+        (10, "segment_cache"),
+        (11, "page_reset"),
+        (12, "abandoned_page_purge"),
+        (13, "segment_reset"),
+        (14, "eager_commit_delay"),
+        (21, "max_segment_reclaim"),
+        (25, "purge_extend_delay"),
+    ];
+
+    #[test]
+    fn it_covers_every_option() {
+        for raw in 0.._mi_option_last {
+            let deprecated = DEPRECATED.iter().find(|(slot, _)| *slot == raw);
+            match (MiOption::from_raw(raw), deprecated) {
+                (Some(option), Some((_, name))) => {
+                    panic!("{} is deprecated as {}", option, name)
+                }
+                (None, None) => panic!("option {} is missing", raw),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            MiOption::all().count(),
+            _mi_option_last as usize - DEPRECATED.len()
+        );
+    }
+
+    #[test]
+    fn it_sets_numbers() {
+        let old = MiOption::MaxWarnings.get();
+        MiOption::MaxWarnings.set(7);
+        assert_eq!(MiOption::MaxWarnings.get(), 7);
+        assert_eq!(MiOption::MaxWarnings.get_usize(), 7);
+        assert_eq!(MiOption::MaxWarnings.value(), MiOptionValue::Number(7));
+        MiOption::MaxWarnings.set(old);
+    }
+
+    #[test]
+    fn it_sets_sizes_in_bytes() {
+        let old = MiOption::ArenaReserve.get();
+        MiOption::ArenaReserve.set_size(64 << 20);
+        assert_eq!(MiOption::ArenaReserve.get(), 64 << 10);
+        assert_eq!(MiOption::ArenaReserve.get_size(), 64 << 20);
+
+        MiOption::ArenaReserve.set_size((64 << 20) + 1);
+        assert_eq!(MiOption::ArenaReserve.get_size(), (64 << 20) + 1024);
+        MiOption::ArenaReserve.set(old);
+    }
+
+    #[test]
+    fn it_sets_flags() {
+        let old = MiOption::DestroyOnExit.is_enabled();
+        MiOption::DestroyOnExit.set_enabled(true);
+        assert!(MiOption::DestroyOnExit.is_enabled());
+        MiOption::DestroyOnExit.set_enabled(false);
+        assert_eq!(MiOption::DestroyOnExit.value(), MiOptionValue::Bool(false));
+        MiOption::DestroyOnExit.set_enabled(old);
+    }
+
+    #[test]
+    fn it_sets_the_guarded_size_window() {
+        let (min, max) = (
+            MiOption::GuardedMin.get_size(),
+            MiOption::GuardedMax.get_size(),
+        );
+        set_guarded_size_window(16..=1024);
+        assert_eq!(MiOption::GuardedMin.get_size(), 16);
+        assert_eq!(MiOption::GuardedMax.get_size(), 1024);
+        set_guarded_size_window(min..=max);
+    }
+
+    /// Run by `it_faults_on_a_guarded_overflow` in a child process, as the
+    /// overflow kills it.
+    #[cfg(all(feature = "guarded", unix))]
+    #[test]
+    #[ignore = "run in a child process"]
+    fn guarded_overflow_child() {
+        extern crate std;
+        let overflow = std::env::var_os("MIMALLOC_TEST_GUARDED_OVERFLOW").is_some();
+
+        MiOption::GuardedPrecise.set_enabled(true);
+        set_guarded_size_window(64..=64);
+        set_guarded_sample_rate(1, 0);
+        unsafe {
+            let ptr = mi_malloc(64) as *mut u8;
+            assert!(!ptr.is_null());
+            ptr.write_bytes(0xAB, 64);
+            if overflow {
+                // The guard page directly follows the object.
+                for i in 64..64 << 10 {
+                    ptr.add(i).write_volatile(0xAB);
+                }
+            }
+            mi_free(ptr as *mut core::ffi::c_void);
+        }
+    }
+
+    #[cfg(all(feature = "guarded", unix))]
+    #[test]
+    fn it_faults_on_a_guarded_overflow() {
+        extern crate std;
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::{Command, Output};
+
+        const SIGSEGV: i32 = 11;
+        #[cfg(target_os = "linux")]
+        const SIGBUS: i32 = 7;
+        #[cfg(not(target_os = "linux"))]
+        const SIGBUS: i32 = 10;
+
+        let run = |overflow: bool| -> Output {
+            let mut child = Command::new(std::env::current_exe().unwrap());
+            child.args([
+                "--ignored",
+                "--exact",
+                "options::tests::guarded_overflow_child",
+                "--test-threads=1",
+            ]);
+            if overflow {
+                child.env("MIMALLOC_TEST_GUARDED_OVERFLOW", "1");
+            }
+            child.output().unwrap()
+        };
+
+        let clean = run(false);
+        assert!(
+            clean.status.success(),
+            "the in-bounds run failed: {:?}",
+            clean
+        );
+        let overflow = run(true);
+        assert!(
+            matches!(overflow.status.signal(), Some(SIGSEGV) | Some(SIGBUS)),
+            "the overflow did not fault: {:?}",
+            overflow
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use crate::callback;
+use crate::MiMalloc;
+use core::cell::UnsafeCell;
+use core::ffi::{c_char, c_void, CStr};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use ffi::*;
+
+/// Route mimalloc's output, like warnings, verbose messages and statistics,
+/// through `sink` instead of stderr.
+///
+/// mimalloc may call `sink` from any thread, possibly concurrently, so it
+/// should be cheap and must not panic; a panic aborts the process. Messages
+/// are usually, but not always, single lines ending in `\n`.
+///
+/// The sink is moved into a mimalloc allocation that is never freed, since
+/// mimalloc may still be calling it after a new sink is registered.
+///
+/// # Panics
+/// Panics if mimalloc fails to allocate room for the sink.
+///
+/// ## Usage
+/// ```rust
+/// mimalloc::set_output(|msg| {
+///     // Forward to a logger of your choice.
+///     let _ = msg.trim_end();
+/// });
+/// ```
+pub fn set_output<F>(sink: F)
+where
+    F: Fn(&str) + Send + Sync + 'static,
+{
+    let arg = callback::leak(sink, call_sink::<F>);
+    unsafe { mi_register_output(Some(output), arg) }
+}
+
+/// Route mimalloc's output through a [`fmt::Write`] implementor.
+///
+/// Writes are serialized with a spin lock, so `writer` must not itself cause
+/// mimalloc to produce output. Write errors are ignored. See [`set_output`]
+/// for details.
+///
+/// # Panics
+/// Panics if mimalloc fails to allocate room for the writer.
+pub fn set_output_writer<W>(writer: W)
+where
+    W: fmt::Write + Send + 'static,
+{
+    let writer = SpinLock::new(writer);
+    set_output(move |msg| {
+        writer.with(|writer| {
+            let _ = writer.write_str(msg);
+        })
+    });
+}
+
+/// Restore mimalloc's default output to stderr.
+///
+/// A previously registered sink is not dropped.
+pub fn reset_output() {
+    unsafe { mi_register_output(None, core::ptr::null_mut()) }
+}
+
+impl MiMalloc {
+    /// Print the process-wide statistics to the output registered with
+    /// [`set_output`], or stderr by default.
+    pub fn print_stats() {
+        unsafe { mi_stats_print_out(None, core::ptr::null_mut()) }
+    }
+
+    /// Print the process-wide statistics into `writer`.
+    pub fn print_stats_to<W: fmt::Write>(writer: &mut W) -> fmt::Result {
+        print_to(writer, |out, arg| unsafe { mi_stats_print_out(out, arg) })
+    }
+
+    /// Print the statistics of the current thread into `writer`.
+    pub fn print_thread_stats_to<W: fmt::Write>(writer: &mut W) -> fmt::Result {
+        print_to(writer, |out, arg| unsafe {
+            mi_thread_stats_print_out(out, arg)
+        })
+    }
+}
+
+/// Convert a message from mimalloc, dropping any invalid UTF-8 suffix.
+unsafe fn message<'a>(msg: *const c_char) -> &'a str {
+    let bytes = CStr::from_ptr(msg).to_bytes();
+    match core::str::from_utf8(bytes) {
+        Ok(msg) => msg,
+        Err(err) => core::str::from_utf8_unchecked(&bytes[..err.valid_up_to()]),
+    }
+}
+
+unsafe extern "C" fn output(msg: *const c_char, arg: *mut c_void) {
+    if !msg.is_null() {
+        callback::invoke(arg, msg);
+    }
+}
+
+unsafe fn call_sink<F: Fn(&str)>(arg: *const c_void, msg: *const c_char) {
+    let sink = callback::get::<*const c_char, F>(arg);
+    sink(message(msg));
+}
+
+struct PrintTo<'a, W> {
+    writer: &'a mut W,
+    result: fmt::Result,
+}
+
+fn print_to<W: fmt::Write>(
+    writer: &mut W,
+    print: impl FnOnce(mi_output_fun, *mut c_void),
+) -> fmt::Result {
+    let mut state = PrintTo {
+        writer,
+        result: Ok(()),
+    };
+    print(
+        Some(write_message::<W>),
+        &mut state as *mut PrintTo<'_, W> as *mut c_void,
+    );
+    state.result
+}
+
+unsafe extern "C" fn write_message<W: fmt::Write>(msg: *const c_char, arg: *mut c_void) {
+    if msg.is_null() || arg.is_null() {
+        return;
+    }
+    let state = &mut *(arg as *mut PrintTo<'_, W>);
+    if state.result.is_ok() {
+        state.result = state.writer.write_str(message(msg));
+    }
+}
+
+struct SpinLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// Safety: access to `value` is serialized by `locked`.
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    fn new(value: T) -> Self {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.value.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::sync::atomic::AtomicUsize;
+    use std::string::String;
+
+    #[test]
+    fn it_prints_stats_to_writer() {
+        // mimalloc skips the statistics until something has been allocated.
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptr = unsafe { MiMalloc.alloc(layout) };
+        assert!(!ptr.is_null());
+
+        let mut buffer = String::new();
+        MiMalloc::print_stats_to(&mut buffer).unwrap();
+        assert!(buffer.contains("reserved"));
+
+        let mut buffer = String::new();
+        MiMalloc::print_thread_stats_to(&mut buffer).unwrap();
+        assert!(!buffer.is_empty());
+
+        unsafe { MiMalloc.dealloc(ptr, layout) };
+    }
+
+    #[test]
+    fn it_reports_write_errors() {
+        struct Failing;
+        impl fmt::Write for Failing {
+            fn write_str(&mut self, _: &str) -> fmt::Result {
+                Err(fmt::Error)
+            }
+        }
+        assert!(MiMalloc::print_stats_to(&mut Failing).is_err());
+    }
+
+    #[test]
+    fn it_routes_output_to_sink() {
+        static BYTES: AtomicUsize = AtomicUsize::new(0);
+        set_output(|msg| {
+            BYTES.fetch_add(msg.len(), Ordering::Relaxed);
+        });
+        MiMalloc::print_stats();
+        assert!(BYTES.load(Ordering::Relaxed) > 0);
+
+        // Both sinks share the global registration, so test them together.
+        static LINES: AtomicUsize = AtomicUsize::new(0);
+        struct Lines;
+        impl fmt::Write for Lines {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                LINES.fetch_add(s.matches('\n').count(), Ordering::Relaxed);
+                Ok(())
+            }
+        }
+        set_output_writer(Lines);
+        MiMalloc::print_stats();
+        reset_output();
+        assert!(LINES.load(Ordering::Relaxed) > 1);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::MiHeap;
+use core::alloc::Layout;
+use core::mem::ManuallyDrop;
+use core::ops::ControlFlow;
+use core::ptr;
+use ffi::*;
+
+impl MiHeap {
+    /// Run `f` with a fresh heap whose allocations are all freed at once when
+    /// `f` returns.
+    ///
+    /// Everything allocated through the [`MiHeapScope`] is released in one go
+    /// with `mi_heap_destroy`, which makes this a cheap arena for short-lived
+    /// data such as per-request parsing. With v2 builds that have guard pages
+    /// (the `guarded` and `debug` features) the blocks are freed one by one
+    /// instead, which is slower. Scope allocations never get guard pages.
+    /// Allocations borrow the scope, so the
+    /// borrow checker prevents them from outliving it:
+    ///
+    /// ```rust
+    /// use mimalloc::MiHeap;
+    ///
+    /// let total = MiHeap::scope(|scope| {
+    ///     let a = scope.alloc(40u64);
+    ///     let b = scope.alloc_slice_copy(&[1u64, 1]);
+    ///     *a + b.iter().sum::<u64>()
+    /// });
+    /// assert_eq!(total, 42);
+    /// ```
+    ///
+    /// ```rust,compile_fail
+    /// use mimalloc::MiHeap;
+    ///
+    /// let escaped = MiHeap::scope(|scope| scope.alloc(42u64));
+    /// ```
+    ///
+    /// Destructors of values allocated in the scope are **not** run when it
+    /// ends; only their memory is reclaimed.
+    ///
+    /// # Panics
+    /// Panics if mimalloc fails to create the heap.
+    pub fn scope<F, R>(f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope MiHeapScope) -> R,
+    {
+        let scope = MiHeapScope {
+            heap: ManuallyDrop::new(MiHeap::new()),
+        };
+        f(&scope)
+    }
+}
+
+/// A heap whose allocations are freed all at once, see [`MiHeap::scope`].
+///
+/// With the `nightly_allocator_api` feature, `&MiHeapScope` also implements
+/// `Allocator`, so collections like `Vec<T, &MiHeapScope>` can be built in
+/// the scope.
+#[derive(Debug)]
+pub struct MiHeapScope {
+    pub(crate) heap: ManuallyDrop<MiHeap>,
+}
+
+impl MiHeapScope {
+    /// Move `value` into the scope and return a reference to it.
+    ///
+    /// # Panics
+    /// Panics if out of memory, see [`MiHeapScope::try_alloc`] for a
+    /// non-panicking variant.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        match self.try_alloc(value) {
+            Ok(value) => value,
+            Err(_) => panic!("mimalloc failed to allocate in heap scope"),
+        }
+    }
+
+    /// Move `value` into the scope and return a reference to it, or give the
+    /// value back if out of memory.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc<T>(&self, value: T) -> Result<&mut T, T> {
+        let ptr = self.heap().alloc(Layout::new::<T>()) as *mut T;
+        if ptr.is_null() {
+            return Err(value);
+        }
+        // Safety: `ptr` is a fresh, properly aligned allocation for a `T`
+        // that lives until the scope is destroyed.
+        unsafe {
+            ptr.write(value);
+            Ok(&mut *ptr)
+        }
+    }
+
+    /// Copy `src` into the scope and return a reference to the copy.
+    ///
+    /// # Panics
+    /// Panics if out of memory.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> &mut [T] {
+        let layout = Layout::for_value(src);
+        let ptr = self.heap().alloc(layout) as *mut T;
+        if ptr.is_null() {
+            panic!("mimalloc failed to allocate in heap scope");
+        }
+        // Safety: `ptr` is a fresh allocation large enough for `src.len()`
+        // elements that lives until the scope is destroyed.
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+            core::slice::from_raw_parts_mut(ptr, src.len())
+        }
+    }
+
+    /// Copy `src` into the scope and return a reference to the copy.
+    ///
+    /// # Panics
+    /// Panics if out of memory.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, src: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(src.as_bytes());
+        // Safety: the bytes were copied from a valid `str`.
+        unsafe { core::str::from_utf8_unchecked_mut(bytes) }
+    }
+}
+
+impl MiHeapScope {
+    /// The heap to allocate from, without guard pages for the calling thread,
+    /// since `mi_heap_destroy` would leave them protected for the next pages
+    /// allocated in their place.
+    #[inline]
+    pub(crate) fn heap(&self) -> &MiHeap {
+        if MI_GUARDED != Some(false) {
+            // Safety: the heap is alive until the scope is dropped.
+            unsafe { disable_guard_pages(self.heap.as_ptr()) }
+        }
+        &self.heap
+    }
+}
+
+#[cfg(feature = "v2")]
+unsafe fn disable_guard_pages(heap: *mut mi_heap_t) {
+    mi_heap_guarded_set_sample_rate(heap, 0, 0);
+}
+
+#[cfg(not(feature = "v2"))]
+unsafe fn disable_guard_pages(heap: *mut mi_heap_t) {
+    // Each thread allocates from its own theap, which picks up the global
+    // sample rate when it is created.
+    mi_theap_guarded_set_sample_rate(mi_heap_theap(heap), 0, 0);
+}
+
+impl Drop for MiHeapScope {
+    fn drop(&mut self) {
+        // Safety: every allocation borrows the scope, so none can be used
+        // after this point. Both paths also release the heap itself, which is
+        // why the inner `MiHeap` is never dropped.
+        unsafe {
+            if cfg!(feature = "v2") && MI_GUARDED != Some(false) {
+                // With `MI_GUARDED` (implied by `debug`), v2 turns
+                // `mi_heap_destroy` into `mi_heap_delete`, which miscounts the
+                // full pages it moves. Free every block so none are left.
+                free_blocks(&self.heap);
+                mi_heap_delete(self.heap.as_ptr());
+            } else {
+                mi_heap_destroy(self.heap.as_ptr());
+            }
+        }
+    }
+}
+
+/// Free every block in `heap`, collecting them in batches as blocks cannot be
+/// freed while the heap is visited.
+///
+/// # Safety
+/// None of the blocks may be used afterwards.
+unsafe fn free_blocks(heap: &MiHeap) {
+    const BATCH: usize = 256;
+    let mut blocks = [ptr::null_mut::<u8>(); BATCH];
+    loop {
+        let mut len = 0;
+        let _ = heap.visit_blocks(|_, block, _| {
+            blocks[len] = block.as_ptr();
+            len += 1;
+            if len == BATCH {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        for &block in &blocks[..len] {
+            mi_free(block.cast());
+        }
+        if len < BATCH {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_allocates_in_scope() {
+        let sum = MiHeap::scope(|scope| {
+            let values: [&mut u64; 3] = [scope.alloc(1), scope.alloc(2), scope.alloc(3)];
+            *values[0] += 10;
+            values.iter().map(|v| **v).sum::<u64>()
+        });
+        assert_eq!(sum, 16);
+    }
+
+    #[test]
+    fn it_copies_slices_and_strs() {
+        MiHeap::scope(|scope| {
+            let words = scope.alloc_str("hello scope");
+            words.make_ascii_uppercase();
+            assert_eq!(words, "HELLO SCOPE");
+
+            let empty: &mut [u32] = scope.alloc_slice_copy(&[]);
+            assert!(empty.is_empty());
+        });
+    }
+
+    #[test]
+    fn it_allocates_many_blocks() {
+        let len = MiHeap::scope(|scope| {
+            let mut total = 0;
+            for i in 0..10_000usize {
+                total += scope.alloc_slice_copy(&[i as u8; 64]).len();
+            }
+            total
+        });
+        assert_eq!(len, 640_000);
+    }
+
+    /// Run by `it_never_guards_scope_allocations` in a child process, as it
+    /// guards every allocation of the process.
+    #[cfg(feature = "guarded")]
+    #[test]
+    #[ignore = "run in a child process"]
+    fn guarded_scopes_child() {
+        crate::options::set_guarded_size_window(0..=1 << 20);
+        crate::options::set_guarded_sample_rate(1, 0);
+        // Pages of a destroyed scope are reused by the next one.
+        for _ in 0..4 {
+            MiHeap::scope(|scope| {
+                for i in 0..1_000usize {
+                    scope.alloc_slice_copy(&[i as u8; 64]);
+                }
+            });
+        }
+    }
+
+    #[cfg(feature = "guarded")]
+    #[test]
+    fn it_never_guards_scope_allocations() {
+        extern crate std;
+        use std::process::Command;
+
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--ignored",
+                "--exact",
+                "scope::tests::guarded_scopes_child",
+                "--test-threads=1",
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "the child failed: {:?}", output);
+    }
+}
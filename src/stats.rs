@@ -0,0 +1,917 @@
+//! Typed process-wide allocation statistics and resource usage.
+//!
+//! [`MiStats`] is collected with [`MiMalloc::stats`]. With mimalloc v3 it is
+//! parsed from the output of `mi_stats_get_json`; with mimalloc v2 it is
+//! parsed from the text printed by `mi_stats_print_out`, which rounds sizes
+//! to one decimal of their unit (e.g. `1.5 MiB`), so v2 values are only
+//! approximate.
+//!
+//! Per-bin and `malloc_*` statistics are only tracked by mimalloc when it is
+//! built with statistics enabled (e.g. the `debug` feature); otherwise they
+//! read as zero.
+
+use crate::MiMalloc;
+use core::time::Duration;
+
+/// The number of size bins tracked by mimalloc (`MI_BIN_HUGE + 1`).
+pub const BIN_COUNT: usize = 74;
+
+/// The number of chunk bins tracked by mimalloc v3 (`MI_CBIN_COUNT`).
+#[cfg(not(feature = "v2"))]
+pub const CHUNK_BIN_COUNT: usize = 6;
+
+/// A statistic that tracks a running total, its peak and current value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MiStatCount {
+    /// Sum of all increases over the lifetime of the process.
+    pub total: i64,
+    /// Highest value reached so far.
+    pub peak: i64,
+    /// Value at the time of the snapshot.
+    pub current: i64,
+}
+
+impl MiStatCount {
+    fn set(&mut self, field: &str, value: i64) {
+        match field {
+            "total" => self.total = value,
+            "peak" => self.peak = value,
+            "current" => self.current = value,
+            _ => {}
+        }
+    }
+}
+
+/// Statistics of a single size bin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MiBinStats {
+    /// Number of blocks (or pages, for page bins) in this bin.
+    pub count: MiStatCount,
+    /// Size of the blocks in this bin.
+    pub block_size: usize,
+    /// Size of the pages serving this bin, or 0 for huge blocks.
+    pub page_size: usize,
+}
+
+impl MiBinStats {
+    fn set(&mut self, field: &str, value: i64) {
+        match field {
+            "block_size" => self.block_size = value as usize,
+            "page_size" => self.page_size = value as usize,
+            _ => self.count.set(field, value),
+        }
+    }
+}
+
+/// A snapshot of the process-wide mimalloc statistics.
+///
+/// Sizes are in bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MiStats {
+    /// The mimalloc version, see [`MiMalloc::version`].
+    pub version: u32,
+    /// Time elapsed since the process started.
+    pub elapsed: Duration,
+    /// Virtual memory reserved from the OS.
+    pub reserved: MiStatCount,
+    /// Memory committed from the OS.
+    pub committed: MiStatCount,
+    /// Memory committed to pages that have been touched.
+    pub page_committed: MiStatCount,
+    /// Total amount of memory reset.
+    pub reset: i64,
+    /// Total amount of memory purged.
+    pub purged: i64,
+    /// Number of mimalloc pages.
+    pub pages: MiStatCount,
+    /// Number of pages abandoned by threads that exited.
+    pub pages_abandoned: MiStatCount,
+    /// Number of times a page was extended with more free blocks.
+    pub pages_extended: i64,
+    /// Number of pages retired once they were empty.
+    pub pages_retire: i64,
+    /// Number of threads that use mimalloc.
+    pub threads: MiStatCount,
+    /// Number of heaps, not counting their thread-local parts.
+    #[cfg(not(feature = "v2"))]
+    pub heaps: MiStatCount,
+    /// Number of segments.
+    #[cfg(feature = "v2")]
+    pub segments: MiStatCount,
+    /// Number of segments abandoned by threads that exited.
+    #[cfg(feature = "v2")]
+    pub segments_abandoned: MiStatCount,
+    /// Number of segments in the segment cache.
+    #[cfg(feature = "v2")]
+    pub segments_cache: MiStatCount,
+    /// Bytes in small and large (binned) allocations.
+    pub malloc_normal: MiStatCount,
+    /// Bytes in huge allocations.
+    pub malloc_huge: MiStatCount,
+    /// Bytes requested by the program, before rounding up to a bin.
+    pub malloc_requested: MiStatCount,
+    /// Number of small and large (binned) allocations.
+    pub malloc_normal_count: i64,
+    /// Number of huge allocations.
+    pub malloc_huge_count: i64,
+    /// Number of allocations placed in front of a guard page, see the
+    /// `guarded` feature.
+    pub malloc_guarded_count: i64,
+    /// Number of arenas created.
+    pub arena_count: i64,
+    /// Number of arena allocations that were rolled back.
+    pub arena_rollback_count: i64,
+    /// Number of calls to map memory from the OS.
+    pub mmap_calls: i64,
+    /// Number of calls to commit memory.
+    pub commit_calls: i64,
+    /// Number of calls to reset memory.
+    pub reset_calls: i64,
+    /// Number of calls to purge memory.
+    pub purge_calls: i64,
+    /// Live blocks per size bin.
+    pub malloc_bins: [MiBinStats; BIN_COUNT],
+    /// Pages per size bin.
+    pub page_bins: [MiBinStats; BIN_COUNT],
+    /// Chunks per chunk bin (small, other, medium, large, huge, none).
+    #[cfg(not(feature = "v2"))]
+    pub chunk_bins: [MiStatCount; CHUNK_BIN_COUNT],
+}
+
+impl Default for MiStats {
+    fn default() -> Self {
+        MiStats {
+            version: 0,
+            elapsed: Duration::from_millis(0),
+            reserved: MiStatCount::default(),
+            committed: MiStatCount::default(),
+            page_committed: MiStatCount::default(),
+            reset: 0,
+            purged: 0,
+            pages: MiStatCount::default(),
+            pages_abandoned: MiStatCount::default(),
+            pages_extended: 0,
+            pages_retire: 0,
+            threads: MiStatCount::default(),
+            #[cfg(not(feature = "v2"))]
+            heaps: MiStatCount::default(),
+            #[cfg(feature = "v2")]
+            segments: MiStatCount::default(),
+            #[cfg(feature = "v2")]
+            segments_abandoned: MiStatCount::default(),
+            #[cfg(feature = "v2")]
+            segments_cache: MiStatCount::default(),
+            malloc_normal: MiStatCount::default(),
+            malloc_huge: MiStatCount::default(),
+            malloc_requested: MiStatCount::default(),
+            malloc_normal_count: 0,
+            malloc_huge_count: 0,
+            malloc_guarded_count: 0,
+            arena_count: 0,
+            arena_rollback_count: 0,
+            mmap_calls: 0,
+            commit_calls: 0,
+            reset_calls: 0,
+            purge_calls: 0,
+            malloc_bins: [MiBinStats::default(); BIN_COUNT],
+            page_bins: [MiBinStats::default(); BIN_COUNT],
+            #[cfg(not(feature = "v2"))]
+            chunk_bins: [MiStatCount::default(); CHUNK_BIN_COUNT],
+        }
+    }
+}
+
+impl MiStats {
+    /// Parse statistics in the format produced by `mi_stats_get_json`.
+    ///
+    /// Unknown fields are ignored, so the output of other mimalloc versions
+    /// can be parsed as well; fields missing from the JSON are left at zero.
+    pub fn from_json(json: &str) -> Result<MiStats, &'static str> {
+        let mut stats = MiStats::default();
+        let mut parser = Json {
+            src: json.as_bytes(),
+            pos: 0,
+        };
+        let mut path = Path {
+            segments: [Segment::Index(0); MAX_DEPTH],
+            depth: 0,
+        };
+        parser.value(&mut path, &mut stats)?;
+        if parser.peek().is_some() {
+            return Err(MALFORMED_JSON);
+        }
+        Ok(stats)
+    }
+
+    fn count_mut(&mut self, name: &str) -> Option<&mut MiStatCount> {
+        Some(match name {
+            "reserved" => &mut self.reserved,
+            "committed" => &mut self.committed,
+            "page_committed" => &mut self.page_committed,
+            "pages" => &mut self.pages,
+            "pages_abandoned" => &mut self.pages_abandoned,
+            "threads" => &mut self.threads,
+            #[cfg(not(feature = "v2"))]
+            "heaps" => &mut self.heaps,
+            #[cfg(feature = "v2")]
+            "segments" => &mut self.segments,
+            #[cfg(feature = "v2")]
+            "segments_abandoned" => &mut self.segments_abandoned,
+            #[cfg(feature = "v2")]
+            "segments_cache" => &mut self.segments_cache,
+            "malloc_normal" => &mut self.malloc_normal,
+            "malloc_huge" => &mut self.malloc_huge,
+            "malloc_requested" => &mut self.malloc_requested,
+            _ => return None,
+        })
+    }
+
+    fn counter_mut(&mut self, name: &str) -> Option<&mut i64> {
+        Some(match name {
+            "reset" => &mut self.reset,
+            "purged" => &mut self.purged,
+            "pages_extended" => &mut self.pages_extended,
+            "pages_retire" => &mut self.pages_retire,
+            "malloc_normal_count" => &mut self.malloc_normal_count,
+            "malloc_huge_count" => &mut self.malloc_huge_count,
+            "malloc_guarded_count" => &mut self.malloc_guarded_count,
+            "arena_count" => &mut self.arena_count,
+            "arena_rollback_count" => &mut self.arena_rollback_count,
+            "mmap_calls" => &mut self.mmap_calls,
+            "commit_calls" => &mut self.commit_calls,
+            "reset_calls" => &mut self.reset_calls,
+            "purge_calls" => &mut self.purge_calls,
+            _ => return None,
+        })
+    }
+
+    fn record(&mut self, path: &[Segment<'_>], value: i64) {
+        match *path {
+            [Segment::Key("mimalloc_version")] => self.version = value as u32,
+            [Segment::Key("process"), Segment::Key("elapsed_msecs")] => {
+                self.elapsed = Duration::from_millis(value as u64)
+            }
+            [Segment::Key(name)] => {
+                if let Some(counter) = self.counter_mut(name) {
+                    *counter = value;
+                }
+            }
+            [Segment::Key(name), Segment::Key(field)] => {
+                if let Some(count) = self.count_mut(name) {
+                    count.set(field, value);
+                }
+            }
+            [Segment::Key(name), Segment::Index(i), Segment::Key(field)] => {
+                let bins = match name {
+                    "malloc_bins" => &mut self.malloc_bins,
+                    "page_bins" => &mut self.page_bins,
+                    #[cfg(not(feature = "v2"))]
+                    "chunk_bins" => {
+                        if let Some(bin) = self.chunk_bins.get_mut(i) {
+                            bin.set(field, value);
+                        }
+                        return;
+                    }
+                    _ => return,
+                };
+                if let Some(bin) = bins.get_mut(i) {
+                    bin.set(field, value);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A snapshot of process-level resource usage, see [`MiMalloc::process_info`].
+///
+/// The RSS is precise on Windows and macOS; other systems estimate it from
+/// the committed memory. Commit is precise on Windows but estimated elsewhere
+/// as the read/write accessible memory reserved by mimalloc.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessInfo {
+    /// Wall-clock time since the process started.
+    pub elapsed: Duration,
+    /// User time, summed over all threads.
+    pub user_time: Duration,
+    /// System time, summed over all threads.
+    pub system_time: Duration,
+    /// Current working set size in bytes.
+    pub current_rss: usize,
+    /// Peak working set size in bytes.
+    pub peak_rss: usize,
+    /// Current committed memory in bytes.
+    pub current_commit: usize,
+    /// Peak committed memory in bytes.
+    pub peak_commit: usize,
+    /// Number of hard page faults.
+    pub page_faults: usize,
+}
+
+impl ProcessInfo {
+    /// Return the change since an `earlier` snapshot.
+    ///
+    /// Times and page faults saturate at zero if `earlier` was in fact taken
+    /// later.
+    pub fn delta(&self, earlier: &ProcessInfo) -> ProcessInfoDelta {
+        ProcessInfoDelta {
+            elapsed: duration_sub(self.elapsed, earlier.elapsed),
+            user_time: duration_sub(self.user_time, earlier.user_time),
+            system_time: duration_sub(self.system_time, earlier.system_time),
+            rss: self.current_rss as isize - earlier.current_rss as isize,
+            peak_rss: self.peak_rss.saturating_sub(earlier.peak_rss),
+            commit: self.current_commit as isize - earlier.current_commit as isize,
+            peak_commit: self.peak_commit.saturating_sub(earlier.peak_commit),
+            page_faults: self.page_faults.saturating_sub(earlier.page_faults),
+        }
+    }
+}
+
+fn duration_sub(later: Duration, earlier: Duration) -> Duration {
+    later
+        .checked_sub(earlier)
+        .unwrap_or_else(|| Duration::from_millis(0))
+}
+
+/// The change between two [`ProcessInfo`] snapshots, see [`ProcessInfo::delta`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessInfoDelta {
+    /// Wall-clock time between the snapshots.
+    pub elapsed: Duration,
+    /// User time spent between the snapshots.
+    pub user_time: Duration,
+    /// System time spent between the snapshots.
+    pub system_time: Duration,
+    /// Change of the current working set size in bytes.
+    pub rss: isize,
+    /// Growth of the peak working set size in bytes.
+    pub peak_rss: usize,
+    /// Change of the current committed memory in bytes.
+    pub commit: isize,
+    /// Growth of the peak committed memory in bytes.
+    pub peak_commit: usize,
+    /// Number of hard page faults between the snapshots.
+    pub page_faults: usize,
+}
+
+impl MiMalloc {
+    /// Return the current resource usage of the process.
+    pub fn process_info() -> ProcessInfo {
+        let mut elapsed = 0;
+        let mut user = 0;
+        let mut system = 0;
+        let mut info = ProcessInfo::default();
+        unsafe {
+            ffi::mi_process_info(
+                &mut elapsed,
+                &mut user,
+                &mut system,
+                &mut info.current_rss,
+                &mut info.peak_rss,
+                &mut info.current_commit,
+                &mut info.peak_commit,
+                &mut info.page_faults,
+            );
+        }
+        info.elapsed = Duration::from_millis(elapsed as u64);
+        info.user_time = Duration::from_millis(user as u64);
+        info.system_time = Duration::from_millis(system as u64);
+        info
+    }
+
+    /// Collect a snapshot of the process-wide statistics.
+    ///
+    /// The statistics are parsed from the JSON of `mi_stats_get_json`, so all
+    /// values are exact. Per-bin and `malloc_*` statistics are only tracked
+    /// when mimalloc is built with statistics enabled, e.g. with the `debug`
+    /// feature.
+    #[cfg(not(feature = "v2"))]
+    pub fn stats() -> Result<MiStats, &'static str> {
+        let json = MiMalloc::stats_json()?;
+        let json = json.to_str().map_err(|_| MALFORMED_JSON)?;
+        MiStats::from_json(json)
+    }
+
+    /// Collect a snapshot of the process-wide statistics.
+    ///
+    /// With mimalloc v2 the statistics are parsed from the human readable
+    /// output of `mi_stats_print_out`, so sizes are rounded and per-bin
+    /// statistics are only available in debug builds of mimalloc.
+    #[cfg(feature = "v2")]
+    pub fn stats() -> Result<MiStats, &'static str> {
+        text::collect()
+    }
+}
+
+const MALFORMED_JSON: &str = "malformed mimalloc statistics JSON";
+
+const MAX_DEPTH: usize = 4;
+
+#[derive(Clone, Copy)]
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+struct Path<'a> {
+    segments: [Segment<'a>; MAX_DEPTH],
+    depth: usize,
+}
+
+impl<'a> Path<'a> {
+    fn push(&mut self, segment: Segment<'a>) -> Result<(), &'static str> {
+        let slot = self.segments.get_mut(self.depth).ok_or(MALFORMED_JSON)?;
+        *slot = segment;
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn as_slice(&self) -> &[Segment<'a>] {
+        &self.segments[..self.depth]
+    }
+}
+
+/// A minimal JSON reader for the subset emitted by `mi_stats_get_json`:
+/// objects, arrays, strings without escapes and integers.
+struct Json<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Json<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(b) = self.src.get(self.pos) {
+            if !b.is_ascii_whitespace() {
+                return Some(*b);
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    fn eat(&mut self, expected: u8) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), &'static str> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(MALFORMED_JSON)
+        }
+    }
+
+    fn string(&mut self) -> Result<&'a str, &'static str> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        let len = self.src[start..]
+            .iter()
+            .position(|&b| b == b'"' || b == b'\\')
+            .ok_or(MALFORMED_JSON)?;
+        self.pos += len;
+        self.expect(b'"')?;
+        core::str::from_utf8(&self.src[start..start + len]).map_err(|_| MALFORMED_JSON)
+    }
+
+    fn number(&mut self) -> Result<i64, &'static str> {
+        let negative = self.eat(b'-');
+        let start = self.pos;
+        let mut value: i64 = 0;
+        while let Some(digit) = self.src.get(self.pos).filter(|b| b.is_ascii_digit()) {
+            value = value
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(i64::from(digit - b'0')))
+                .ok_or(MALFORMED_JSON)?;
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(MALFORMED_JSON);
+        }
+        Ok(if negative { -value } else { value })
+    }
+
+    fn value(&mut self, path: &mut Path<'a>, stats: &mut MiStats) -> Result<(), &'static str> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                if self.eat(b'}') {
+                    return Ok(());
+                }
+                loop {
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    path.push(Segment::Key(key))?;
+                    self.value(path, stats)?;
+                    path.pop();
+                    if !self.eat(b',') {
+                        return self.expect(b'}');
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                if self.eat(b']') {
+                    return Ok(());
+                }
+                let mut index = 0;
+                loop {
+                    path.push(Segment::Index(index))?;
+                    self.value(path, stats)?;
+                    path.pop();
+                    index += 1;
+                    if !self.eat(b',') {
+                        return self.expect(b']');
+                    }
+                }
+            }
+            Some(b'"') => self.string().map(|_| ()),
+            _ => {
+                let value = self.number()?;
+                stats.record(path.as_slice(), value);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Collection of v2 statistics from the output of `mi_stats_print_out`.
+#[cfg(feature = "v2")]
+mod text {
+    use super::{MiStatCount, MiStats, BIN_COUNT};
+    use core::ffi::{c_char, c_void, CStr};
+    use core::time::Duration;
+
+    const LINE_CAPACITY: usize = 256;
+
+    #[derive(Clone, Copy)]
+    enum Parent {
+        Segments,
+        Pages,
+    }
+
+    pub(super) struct StatsText {
+        stats: MiStats,
+        line: [u8; LINE_CAPACITY],
+        len: usize,
+        parent: Parent,
+        found: bool,
+    }
+
+    pub(super) fn collect() -> Result<MiStats, &'static str> {
+        let mut text = StatsText::new();
+        unsafe {
+            ffi::mi_stats_print_out(Some(collect_output), &mut text as *mut _ as *mut c_void);
+        }
+        text.finish()
+    }
+
+    unsafe extern "C" fn collect_output(msg: *const c_char, arg: *mut c_void) {
+        if msg.is_null() || arg.is_null() {
+            return;
+        }
+        let text = &mut *(arg as *mut StatsText);
+        text.feed(CStr::from_ptr(msg).to_bytes());
+    }
+
+    impl StatsText {
+        pub(super) fn new() -> Self {
+            StatsText {
+                stats: MiStats::default(),
+                line: [0; LINE_CAPACITY],
+                len: 0,
+                parent: Parent::Segments,
+                found: false,
+            }
+        }
+
+        pub(super) fn feed(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                if b == b'\n' {
+                    self.flush();
+                } else if self.len < LINE_CAPACITY {
+                    self.line[self.len] = b;
+                    self.len += 1;
+                }
+            }
+        }
+
+        pub(super) fn finish(mut self) -> Result<MiStats, &'static str> {
+            self.flush();
+            if !self.found {
+                return Err("failed to parse the output of mi_stats_print_out");
+            }
+            self.stats.version = unsafe { ffi::mi_version() as u32 };
+            Ok(self.stats)
+        }
+
+        fn flush(&mut self) {
+            let len = core::mem::replace(&mut self.len, 0);
+            let line = match core::str::from_utf8(&self.line[..len]) {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            let colon = match line.find(':') {
+                Some(colon) => colon,
+                None => return,
+            };
+            let label = line[..colon].trim();
+            let rest = &line[colon + 1..];
+
+            let mut values = [0i64; 4];
+            let mut decimals = [0u32; 4];
+            let mut n = 0;
+            for token in rest.split_whitespace() {
+                if let Some((value, places)) = parse_decimal(token) {
+                    if n == values.len() {
+                        break;
+                    }
+                    values[n] = value;
+                    decimals[n] = places;
+                    n += 1;
+                } else if let Some(unit) = parse_unit(token) {
+                    if n > 0 {
+                        values[n - 1] = values[n - 1].saturating_mul(unit);
+                    }
+                } else if n > 0 {
+                    break;
+                }
+            }
+            for (value, places) in values.iter_mut().zip(decimals.iter()) {
+                *value /= 10i64.pow(*places);
+            }
+            let values = &values[..n];
+            if values.is_empty() {
+                return;
+            }
+
+            if label == "elapsed" {
+                if let Some((millis, places)) =
+                    rest.split_whitespace().next().and_then(parse_decimal)
+                {
+                    let millis = millis.saturating_mul(1000) / 10i64.pow(places);
+                    self.stats.elapsed = Duration::from_millis(millis as u64);
+                }
+                return;
+            }
+
+            if let Some(bin) = label.strip_prefix("bin") {
+                let bin = match bin.trim().parse::<usize>() {
+                    Ok(bin) if bin < BIN_COUNT => bin,
+                    _ => return,
+                };
+                // Bin amounts are printed in bytes, scaled by the block size.
+                if let [peak, total, current, block_size] = *values {
+                    let block_size = block_size.max(1);
+                    let stats = &mut self.stats.malloc_bins[bin];
+                    stats.block_size = block_size as usize;
+                    stats.count = MiStatCount {
+                        total: total / block_size,
+                        peak: peak / block_size,
+                        current: current / block_size,
+                    };
+                }
+                return;
+            }
+
+            let stats = &mut self.stats;
+            let count = match label {
+                "reserved" => {
+                    self.found = true;
+                    &mut stats.reserved
+                }
+                "committed" => &mut stats.committed,
+                "touched" => &mut stats.page_committed,
+                "segments" => {
+                    self.parent = Parent::Segments;
+                    &mut stats.segments
+                }
+                "-cached" => &mut stats.segments_cache,
+                "pages" => {
+                    self.parent = Parent::Pages;
+                    &mut stats.pages
+                }
+                "-abandoned" => match self.parent {
+                    Parent::Segments => &mut stats.segments_abandoned,
+                    Parent::Pages => &mut stats.pages_abandoned,
+                },
+                "threads" => &mut stats.threads,
+                "binned" => &mut stats.malloc_normal,
+                "huge" => &mut stats.malloc_huge,
+                "malloc req" => &mut stats.malloc_requested,
+                _ => {
+                    let counter = match label {
+                        "reset" => &mut stats.reset,
+                        "purged" => &mut stats.purged,
+                        "-extended" => &mut stats.pages_extended,
+                        "-retire" => &mut stats.pages_retire,
+                        "arenas" => &mut stats.arena_count,
+                        "-rollback" => &mut stats.arena_rollback_count,
+                        "mmaps" => &mut stats.mmap_calls,
+                        "commits" => &mut stats.commit_calls,
+                        "resets" => &mut stats.reset_calls,
+                        "purges" => &mut stats.purge_calls,
+                        "guarded" => &mut stats.malloc_guarded_count,
+                        _ => return,
+                    };
+                    *counter = values[0];
+                    return;
+                }
+            };
+            match *values {
+                [total] => count.total = total,
+                [peak, total, current, ..] => {
+                    *count = MiStatCount {
+                        total,
+                        peak,
+                        current,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Parse `123` or `1.5` into a fixed-point value and its number of
+    /// decimal places.
+    fn parse_decimal(token: &str) -> Option<(i64, u32)> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let mut value: i64 = 0;
+        let mut places = None;
+        for b in digits.bytes() {
+            match b {
+                b'0'..=b'9' => {
+                    value = value.checked_mul(10)?.checked_add(i64::from(b - b'0'))?;
+                    places = places.map(|p: u32| p + 1);
+                }
+                b'.' if places.is_none() => places = Some(0),
+                _ => return None,
+            }
+        }
+        if digits.is_empty() || places == Some(0) {
+            return None;
+        }
+        Some((if negative { -value } else { value }, places.unwrap_or(0)))
+    }
+
+    /// Parse the unit suffixes printed by mimalloc, e.g. `KiB` or `M`.
+    fn parse_unit(token: &str) -> Option<i64> {
+        let token = token.strip_suffix('B').unwrap_or(token);
+        let (magnitude, base) = match token.strip_suffix('i') {
+            Some(magnitude) => (magnitude, 1024),
+            None => (token, 1000),
+        };
+        match magnitude {
+            "" => Some(1),
+            "K" => Some(base),
+            "M" => Some(base * base),
+            "G" => Some(base * base * base),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_stats_json() {
+        let json = r#"{
+          "stat_version": 5,
+          "mimalloc_version": 30302,
+          "process": { "elapsed_msecs": 1500, "user_msecs": 22 },
+          "pages": { "total": 23, "peak": 22, "current": 21 },
+          "reserved": { "total": 1076166656, "peak": 1076166656, "current": 1076166656 },
+          "reset": 4096,
+          "purge_calls": 7,
+          "unknown_stat": { "total": -1, "nested": [1, 2, { "deep": 3 }] },
+          "malloc_bins": [
+            { "total": 0, "peak": 0, "current": 0, "block_size": 8, "page_size": 65536 },
+            { "total": 5, "peak": 4, "current": -3, "block_size": 16, "page_size": 65536 }
+          ],
+          "chunk_bins": [ { "total": 1, "peak": 1, "current": 1, "bin": "S" } ]
+        }"#;
+        let stats = MiStats::from_json(json).unwrap();
+        assert_eq!(stats.version, 30302);
+        assert_eq!(stats.elapsed, Duration::from_millis(1500));
+        assert_eq!(
+            stats.pages,
+            MiStatCount {
+                total: 23,
+                peak: 22,
+                current: 21
+            }
+        );
+        assert_eq!(stats.reserved.current, 1076166656);
+        assert_eq!(stats.reset, 4096);
+        assert_eq!(stats.purge_calls, 7);
+        assert_eq!(stats.malloc_bins[0].block_size, 8);
+        assert_eq!(stats.malloc_bins[1].count.total, 5);
+        assert_eq!(stats.malloc_bins[1].count.current, -3);
+        assert_eq!(stats.malloc_bins[1].page_size, 65536);
+        #[cfg(not(feature = "v2"))]
+        assert_eq!(stats.chunk_bins[0].total, 1);
+    }
+
+    #[test]
+    fn it_rejects_malformed_json() {
+        assert!(MiStats::from_json("").is_err());
+        assert!(MiStats::from_json(r#"{ "reset": }"#).is_err());
+        assert!(MiStats::from_json(r#"{ "reset": 1 "#).is_err());
+        assert!(MiStats::from_json(r#"{ "reset": 1.5 }"#).is_err());
+        assert!(MiStats::from_json(r#"{ "a": { "b": { "c": { "d": [1] } } } }"#).is_err());
+    }
+
+    #[test]
+    fn it_collects_process_stats() {
+        let stats = MiMalloc::stats().unwrap();
+        assert_eq!(stats.version, MiMalloc.version());
+        assert!(stats.reserved.current > 0);
+        assert!(stats.reserved.peak >= stats.reserved.current);
+        assert!(stats.committed.current <= stats.reserved.current);
+    }
+
+    #[test]
+    fn it_reports_process_info() {
+        let before = MiMalloc::process_info();
+        assert!(before.peak_rss > 0);
+
+        let layout = core::alloc::Layout::from_size_align(8 << 20, 8).unwrap();
+        unsafe {
+            let ptr = core::alloc::GlobalAlloc::alloc_zeroed(&MiMalloc, layout);
+            ptr.write_bytes(1, layout.size());
+            let after = MiMalloc::process_info();
+            core::alloc::GlobalAlloc::dealloc(&MiMalloc, ptr, layout);
+
+            let delta = after.delta(&before);
+            assert!(after.elapsed >= before.elapsed);
+            assert_eq!(delta.elapsed, after.elapsed - before.elapsed);
+            assert_eq!(
+                delta.commit,
+                after.current_commit as isize - before.current_commit as isize
+            );
+            assert_eq!(before.delta(&after).user_time, Duration::from_millis(0));
+        }
+    }
+
+    #[cfg(not(feature = "v2"))]
+    #[test]
+    fn it_reports_bin_sizes() {
+        let stats = MiMalloc::stats().unwrap();
+        assert_eq!(stats.malloc_bins[1].block_size, 8);
+        // The last bin collects huge blocks and has no meaningful size.
+        assert!(stats.malloc_bins[..BIN_COUNT - 1]
+            .windows(2)
+            .all(|w| w[0].block_size <= w[1].block_size));
+    }
+
+    #[cfg(feature = "v2")]
+    #[test]
+    fn it_parses_stats_text() {
+        let output = "heap stats:     peak       total     current       block      total#   \n\
+            \x20  bin   2:    80 B       96 B       16 B        16 B        6         not all freed\n\
+            \x20 reserved:     1.0 GiB     1.0 GiB     1.0 GiB                          \n\
+            \x20committed:     1.5 MiB     2.0 MiB     1.0 MiB                          \n\
+            \x20    reset:     0      \n\
+            \x20   purged:    64.0 KiB\n\
+            \x20 segments:     1           1           1      \n\
+            -abandoned:     0           0           0      \n\
+            \x20    pages:     3           5           2      \n\
+            -abandoned:     1           1           1      \n\
+            \x20    mmaps:     1.2 K   \n\
+            \x20  threads:     0           2           0      \n\
+            \x20  elapsed:     1.250 s\n\
+            \x20  process: user: 0.025 s, system: 0.007 s, faults: 0, peak rss: 28.8 MiB\n";
+        let mut text = text::StatsText::new();
+        // Output arrives in arbitrary chunks.
+        for chunk in output.as_bytes().chunks(7) {
+            text.feed(chunk);
+        }
+        let stats = text.finish().unwrap();
+        assert_eq!(stats.reserved.current, 1 << 30);
+        assert_eq!(stats.committed.peak, 3 << 19);
+        assert_eq!(stats.committed.total, 2 << 20);
+        assert_eq!(stats.purged, 64 << 10);
+        assert_eq!(stats.segments.current, 1);
+        assert_eq!(stats.segments_abandoned.current, 0);
+        assert_eq!(stats.pages.total, 5);
+        assert_eq!(stats.pages_abandoned.current, 1);
+        assert_eq!(stats.mmap_calls, 1200);
+        assert_eq!(stats.threads.total, 2);
+        assert_eq!(stats.elapsed, Duration::from_millis(1250));
+        assert_eq!(stats.malloc_bins[2].block_size, 16);
+        assert_eq!(stats.malloc_bins[2].count.peak, 5);
+        assert_eq!(stats.malloc_bins[2].count.current, 1);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::dispatch::{self, is_natural, is_small};
+use crate::MiHeap;
+use core::alloc::Layout;
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+use ffi::*;
+
+/// The thread-local part of a mimalloc heap (`mi_theap_t`), bound to the
+/// thread that obtained it.
+///
+/// Allocating through a `MiThreadHeap` skips the thread-local lookup that
+/// [`MiHeap`] and [`MiMalloc`](crate::MiMalloc) perform on every call, which
+/// helps hot per-thread code that can keep the handle around. A
+/// `MiThreadHeap` is neither `Send` nor `Sync`, since mimalloc only allows a
+/// theap to allocate on its own thread. Memory allocated through it can be
+/// freed on any thread.
+///
+/// Only available with mimalloc v3.
+///
+/// ## Usage
+/// ```rust
+/// use core::alloc::Layout;
+/// use mimalloc::MiHeap;
+///
+/// let heap = MiHeap::new();
+/// let local = heap.thread_heap();
+/// let layout = Layout::new::<u64>();
+/// let ptr = local.alloc(layout);
+/// assert!(!ptr.is_null());
+/// unsafe { local.dealloc(ptr, layout) };
+/// ```
+///
+/// The handle cannot leave its thread:
+/// ```rust,compile_fail
+/// let heap = mimalloc::MiHeap::new();
+/// let local = heap.thread_heap();
+/// std::thread::scope(|s| {
+///     s.spawn(move || drop(local));
+/// });
+/// ```
+#[derive(Debug)]
+pub struct MiThreadHeap<'a> {
+    theap: NonNull<mi_theap_t>,
+    _marker: PhantomData<(&'a MiHeap, *mut ())>,
+}
+
+impl MiHeap {
+    /// Return the thread-local part of this heap for the calling thread.
+    ///
+    /// # Panics
+    /// Panics if mimalloc fails to create it, see [`MiHeap::try_thread_heap`]
+    /// for a non-panicking variant.
+    pub fn thread_heap(&self) -> MiThreadHeap<'_> {
+        self.try_thread_heap()
+            .expect("mimalloc failed to create a thread-local heap")
+    }
+
+    /// Return the thread-local part of this heap for the calling thread, or
+    /// `None` if mimalloc failed to create it.
+    pub fn try_thread_heap(&self) -> Option<MiThreadHeap<'_>> {
+        MiThreadHeap::from_raw(unsafe { mi_heap_theap(self.as_ptr()) })
+    }
+}
+
+impl MiThreadHeap<'_> {
+    fn from_raw(theap: *mut mi_theap_t) -> Option<Self> {
+        let theap = NonNull::new(theap)?;
+        Some(MiThreadHeap {
+            theap,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Call `f` with the default thread-local heap of the calling thread,
+    /// which backs [`MiMalloc`](crate::MiMalloc).
+    pub fn with_default<R, F>(f: F) -> R
+    where
+        F: FnOnce(&MiThreadHeap<'_>) -> R,
+    {
+        // `mi_theap_get_default` initializes the thread if needed, so it
+        // never returns null.
+        let theap = MiThreadHeap::from_raw(unsafe { mi_theap_get_default() })
+            .expect("mimalloc returned no default thread-local heap");
+        f(&theap)
+    }
+
+    /// Return the underlying `mi_theap_t` for use with the raw FFI.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut mi_theap_t {
+        self.theap.as_ptr()
+    }
+
+    /// Allocate memory described by `layout` out of this heap.
+    ///
+    /// Returns null if out of memory. Returns a unique pointer if `layout`
+    /// has a size of 0.
+    #[inline]
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe {
+            if is_small(layout) {
+                mi_theap_malloc_small(self.as_ptr(), layout.size()) as *mut u8
+            } else if is_natural(layout.size(), layout.align()) {
+                mi_theap_malloc(self.as_ptr(), layout.size()) as *mut u8
+            } else {
+                mi_theap_malloc_aligned(self.as_ptr(), layout.size(), layout.align()) as *mut u8
+            }
+        }
+    }
+
+    /// Allocate zero-initialized memory described by `layout` out of this
+    /// heap.
+    ///
+    /// Returns null if out of memory. Returns a unique pointer if `layout`
+    /// has a size of 0.
+    #[inline]
+    pub fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe {
+            if is_small(layout) {
+                mi_theap_zalloc_small(self.as_ptr(), layout.size()) as *mut u8
+            } else if is_natural(layout.size(), layout.align()) {
+                mi_theap_zalloc(self.as_ptr(), layout.size()) as *mut u8
+            } else {
+                let ptr = mi_theap_malloc_aligned(self.as_ptr(), layout.size(), layout.align());
+                if !ptr.is_null() {
+                    ptr::write_bytes(ptr as *mut u8, 0, layout.size());
+                }
+                ptr as *mut u8
+            }
+        }
+    }
+
+    /// Re-allocate memory to `new_size` bytes, keeping the alignment of
+    /// `layout`, in this heap.
+    ///
+    /// Returns null if out of memory, in which case `ptr` is not freed.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by mimalloc with `layout`, or be null.
+    #[inline]
+    pub unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size()
+            && !ptr.is_null()
+            && dispatch::grows_in_place(ptr as *mut c_void, new_size, layout.align())
+        {
+            return ptr;
+        }
+        if is_natural(new_size, layout.align()) {
+            return mi_theap_realloc(self.as_ptr(), ptr as *mut c_void, new_size) as *mut u8;
+        }
+        // mimalloc has no aligned re-allocation for theaps.
+        let new_ptr = mi_theap_malloc_aligned(self.as_ptr(), new_size, layout.align()) as *mut u8;
+        if !new_ptr.is_null() && !ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            mi_free(ptr as *mut c_void);
+        }
+        new_ptr
+    }
+
+    /// Free memory previously allocated by mimalloc.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by mimalloc with `layout`, or be null.
+    #[inline]
+    pub unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        dispatch::free(ptr as *mut c_void, layout);
+    }
+
+    /// Release outstanding resources of this heap on the calling thread.
+    ///
+    /// When `force` is true, all possible memory is released.
+    pub fn collect(&self, force: bool) {
+        unsafe { mi_theap_collect(self.as_ptr(), force) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MiMalloc;
+    use core::alloc::GlobalAlloc;
+    use core::ops::ControlFlow;
+
+    #[test]
+    fn it_allocates_in_its_heap() {
+        let heap = MiHeap::new();
+        let local = heap.thread_heap();
+        assert_eq!(local.as_ptr(), heap.thread_heap().as_ptr());
+
+        let layouts = [
+            Layout::from_size_align(0, 1).unwrap(),
+            Layout::from_size_align(24, 8).unwrap(),
+            Layout::from_size_align(MI_SMALL_SIZE_MAX + 1, 8).unwrap(),
+            Layout::from_size_align(100, 64).unwrap(),
+            Layout::from_size_align(1 << 20, 4096).unwrap(),
+        ];
+        let ptrs = layouts.map(|layout| local.alloc(layout));
+        for (ptr, layout) in ptrs.iter().zip(&layouts) {
+            assert!(!ptr.is_null());
+            assert_eq!(*ptr as usize % layout.align(), 0);
+        }
+
+        let mut found = 0;
+        let _ = heap.visit_blocks(|_, block, size| {
+            // Guarded objects sit at the end of their block.
+            let start = block.as_ptr() as usize;
+            if ptrs
+                .iter()
+                .any(|&ptr| (start..start + size).contains(&(ptr as usize)))
+            {
+                found += 1;
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!(found, ptrs.len());
+
+        for (ptr, layout) in ptrs.iter().zip(&layouts) {
+            unsafe { local.dealloc(*ptr, *layout) };
+        }
+        local.collect(true);
+    }
+
+    #[test]
+    fn it_zeroes_memory() {
+        let heap = MiHeap::new();
+        let local = heap.thread_heap();
+        for layout in [
+            Layout::from_size_align(64, 8).unwrap(),
+            Layout::from_size_align(MI_SMALL_SIZE_MAX * 2, 8).unwrap(),
+            Layout::from_size_align(300, 128).unwrap(),
+        ] {
+            // Dirty a block first, so it may be reused.
+            let ptr = local.alloc(layout);
+            unsafe {
+                ptr.write_bytes(0xAB, layout.size());
+                local.dealloc(ptr, layout);
+            }
+            let ptr = local.alloc_zeroed(layout);
+            assert_eq!(ptr as usize % layout.align(), 0);
+            unsafe {
+                assert!((0..layout.size()).all(|i| *ptr.add(i) == 0));
+                local.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn it_reallocates() {
+        let heap = MiHeap::new();
+        let local = heap.thread_heap();
+        for layout in [
+            Layout::from_size_align(16, 8).unwrap(),
+            Layout::from_size_align(16, 256).unwrap(),
+        ] {
+            unsafe {
+                let ptr = local.alloc(layout);
+                ptr.write_bytes(0x5A, layout.size());
+                let ptr = local.realloc(ptr, layout, 4096);
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % layout.align(), 0);
+                assert!((0..layout.size()).all(|i| *ptr.add(i) == 0x5A));
+                local.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn it_uses_the_default_heap() {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = MiThreadHeap::with_default(|local| local.alloc(layout));
+        assert!(!ptr.is_null());
+        unsafe { MiMalloc.dealloc(ptr, layout) };
+    }
+}
@@ -0,0 +1,271 @@
+use crate::MiHeap;
+use core::ffi::c_void;
+use core::ops::ControlFlow;
+use core::ptr::NonNull;
+use ffi::*;
+
+/// An area of a heap containing blocks of a single size, see
+/// [`MiHeap::visit_areas`].
+#[derive(Clone, Copy, Debug)]
+pub struct HeapArea {
+    area: mi_heap_area_t,
+}
+
+impl HeapArea {
+    /// Start of the area containing the blocks.
+    #[inline]
+    pub fn blocks(&self) -> *mut u8 {
+        self.area.blocks as *mut u8
+    }
+
+    /// Bytes of virtual memory reserved for this area.
+    #[inline]
+    pub fn reserved(&self) -> usize {
+        self.area.reserved
+    }
+
+    /// Bytes currently available in this area.
+    #[inline]
+    pub fn committed(&self) -> usize {
+        self.area.committed
+    }
+
+    /// Number of allocated blocks in this area.
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.area.used
+    }
+
+    /// Size in bytes of each block.
+    #[inline]
+    pub fn block_size(&self) -> usize {
+        self.area.block_size
+    }
+
+    /// Size in bytes of each block, including padding and meta-data.
+    #[inline]
+    pub fn full_block_size(&self) -> usize {
+        self.area.full_block_size
+    }
+
+    /// Fraction of the committed bytes that is not taken by allocated
+    /// blocks, from 0.0 (fully used) to 1.0 (empty).
+    pub fn fragmentation(&self) -> f64 {
+        let committed = self.committed();
+        if committed == 0 {
+            return 0.0;
+        }
+        let used = self.used().saturating_mul(self.full_block_size());
+        1.0 - used.min(committed) as f64 / committed as f64
+    }
+}
+
+impl MiHeap {
+    /// Call `f` for every area of this heap, until it returns
+    /// [`ControlFlow::Break`].
+    ///
+    /// Areas that are allocated or freed concurrently by other threads may or
+    /// may not be visited.
+    ///
+    /// ## Usage
+    /// ```rust
+    /// use core::alloc::Layout;
+    /// use core::ops::ControlFlow;
+    /// use mimalloc::MiHeap;
+    ///
+    /// let heap = MiHeap::new();
+    /// let layout = Layout::new::<u64>();
+    /// let ptr = heap.alloc(layout);
+    ///
+    /// let mut used = 0;
+    /// heap.visit_areas(|area| {
+    ///     used += area.used() * area.block_size();
+    ///     ControlFlow::<()>::Continue(())
+    /// });
+    /// assert!(used >= 8);
+    /// unsafe { heap.dealloc(ptr, layout) };
+    /// ```
+    pub fn visit_areas<B, F>(&self, f: F) -> ControlFlow<B>
+    where
+        F: FnMut(&HeapArea) -> ControlFlow<B>,
+    {
+        let mut visitor = Visitor { f, result: None };
+        unsafe {
+            mi_heap_visit_blocks(
+                self.as_ptr(),
+                false,
+                Some(visit_area::<B, F>),
+                &mut visitor as *mut Visitor<B, F> as *mut c_void,
+            );
+        }
+        visitor.result()
+    }
+
+    /// Call `f` with the area, address and size of every allocated block in
+    /// this heap, until it returns [`ControlFlow::Break`].
+    ///
+    /// Blocks that are allocated or freed concurrently by other threads may
+    /// or may not be visited.
+    pub fn visit_blocks<B, F>(&self, f: F) -> ControlFlow<B>
+    where
+        F: FnMut(&HeapArea, NonNull<u8>, usize) -> ControlFlow<B>,
+    {
+        let mut visitor = Visitor { f, result: None };
+        unsafe {
+            mi_heap_visit_blocks(
+                self.as_ptr(),
+                true,
+                Some(visit_block::<B, F>),
+                &mut visitor as *mut Visitor<B, F> as *mut c_void,
+            );
+        }
+        visitor.result()
+    }
+}
+
+struct Visitor<B, F> {
+    f: F,
+    result: Option<B>,
+}
+
+impl<B, F> Visitor<B, F> {
+    fn result(self) -> ControlFlow<B> {
+        match self.result {
+            Some(result) => ControlFlow::Break(result),
+            None => ControlFlow::Continue(()),
+        }
+    }
+
+    fn apply(&mut self, flow: ControlFlow<B>) -> bool {
+        match flow {
+            ControlFlow::Continue(()) => true,
+            ControlFlow::Break(result) => {
+                self.result = Some(result);
+                false
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn visit_area<B, F>(
+    _heap: *const mi_heap_t,
+    area: *const mi_heap_area_t,
+    block: *mut c_void,
+    _block_size: usize,
+    arg: *mut c_void,
+) -> bool
+where
+    F: FnMut(&HeapArea) -> ControlFlow<B>,
+{
+    if area.is_null() || !block.is_null() {
+        return true;
+    }
+    let visitor = &mut *(arg as *mut Visitor<B, F>);
+    let area = HeapArea { area: *area };
+    let flow = (visitor.f)(&area);
+    visitor.apply(flow)
+}
+
+unsafe extern "C" fn visit_block<B, F>(
+    _heap: *const mi_heap_t,
+    area: *const mi_heap_area_t,
+    block: *mut c_void,
+    block_size: usize,
+    arg: *mut c_void,
+) -> bool
+where
+    F: FnMut(&HeapArea, NonNull<u8>, usize) -> ControlFlow<B>,
+{
+    let block = match NonNull::new(block as *mut u8) {
+        Some(block) if !area.is_null() => block,
+        _ => return true,
+    };
+    let visitor = &mut *(arg as *mut Visitor<B, F>);
+    let area = HeapArea { area: *area };
+    let flow = (visitor.f)(&area, block, block_size);
+    visitor.apply(flow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::alloc::Layout;
+
+    #[test]
+    fn it_visits_areas() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let ptrs: [*mut u8; 16] = core::array::from_fn(|_| heap.alloc(layout));
+
+        let mut areas = 0;
+        let mut used = 0;
+        let flow = heap.visit_areas(|area| {
+            areas += 1;
+            used += area.used();
+            assert!(area.committed() <= area.reserved());
+            assert!(area.block_size() >= 48);
+            assert!((0.0..=1.0).contains(&area.fragmentation()));
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert!(areas >= 1);
+        assert_eq!(used, ptrs.len());
+
+        for ptr in ptrs {
+            unsafe { heap.dealloc(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn it_visits_blocks() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptrs: [*mut u8; 8] = core::array::from_fn(|_| heap.alloc(layout));
+        // Guarded objects sit at the end of their block.
+        let holds_ptr = |block: NonNull<u8>, size: usize| {
+            let start = block.as_ptr() as usize;
+            ptrs.iter()
+                .any(|&ptr| (start..start + size).contains(&(ptr as usize)))
+        };
+
+        let mut found = 0;
+        let flow = heap.visit_blocks(|area, block, size| {
+            assert!(holds_ptr(block, size));
+            assert!(size >= layout.size());
+            assert_eq!(size, area.block_size());
+            found += 1;
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert_eq!(found, ptrs.len());
+
+        // Stop at the first block.
+        let first = heap.visit_blocks(|_, block, size| ControlFlow::Break((block, size)));
+        match first {
+            ControlFlow::Break((block, size)) => assert!(holds_ptr(block, size)),
+            ControlFlow::Continue(()) => panic!("no block visited"),
+        }
+
+        for ptr in ptrs {
+            unsafe { heap.dealloc(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn it_reports_fragmentation() {
+        let heap = MiHeap::new();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptrs = [heap.alloc(layout), heap.alloc(layout)];
+        // A few small blocks leave most of their page unused, unless they
+        // are guarded, in which case each fills a page of its own.
+        let mut fragmentation: f64 = 0.0;
+        let _ = heap.visit_areas(|area| {
+            fragmentation = fragmentation.max(area.fragmentation());
+            ControlFlow::<()>::Continue(())
+        });
+        assert!(fragmentation > 0.5);
+        for ptr in ptrs {
+            unsafe { heap.dealloc(ptr, layout) };
+        }
+    }
+}
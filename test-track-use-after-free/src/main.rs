@@ -0,0 +1,91 @@
+//! Test that with the `track_valgrind` or `track_asan` features, the tool
+//! detects a use-after-free of memory from `MiMalloc`, and that a correct
+//! program runs clean.
+//!
+//! Run with valgrind installed:
+//!
+//! ```sh
+//! cargo run -p test-track-use-after-free \
+//!     --features test-track-use-after-free/valgrind
+//! ```
+//!
+//! Or with ASan:
+//!
+//! ```sh
+//! RUSTFLAGS=-Zsanitizer=address cargo +nightly run \
+//!     -p test-track-use-after-free --features test-track-use-after-free/asan \
+//!     --target x86_64-unknown-linux-gnu
+//! ```
+use mimalloc::MiMalloc;
+use std::env;
+use std::process::{Command, Output};
+
+#[global_allocator]
+static GLOBAL: MiMalloc = MiMalloc;
+
+fn use_after_free() {
+    let block = Box::new([1u64; 4]);
+    let ptr = block.as_ptr();
+    drop(block);
+    let value = unsafe { ptr.read_volatile() };
+    println!("read {} after free", value);
+}
+
+fn clean() {
+    let values: Vec<u64> = (0..1000).collect();
+    let copy = values.clone();
+    drop(values);
+    assert_eq!(copy.iter().sum::<u64>(), 499500);
+}
+
+/// Run this binary with `mode`, under valgrind if that is the tool.
+fn run(mode: &str) -> Output {
+    let exe = env::current_exe().expect("failed to find the test binary");
+    let mut command = if cfg!(feature = "valgrind") {
+        let mut command = Command::new("valgrind");
+        command.args(["--error-exitcode=99", "--quiet"]).arg(exe);
+        command
+    } else {
+        Command::new(exe)
+    };
+    command
+        .arg(mode)
+        .output()
+        .expect("failed to run the test binary")
+}
+
+fn main() {
+    match env::args().nth(1).as_deref() {
+        Some("use-after-free") => return use_after_free(),
+        Some("clean") => return clean(),
+        _ => {}
+    }
+
+    let (tool, report) = if cfg!(feature = "valgrind") {
+        ("valgrind", "Invalid read")
+    } else if cfg!(feature = "asan") {
+        ("ASan", "ERROR: AddressSanitizer")
+    } else {
+        println!("enable the `valgrind` or `asan` feature to test a tool");
+        return;
+    };
+
+    let output = run("clean");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "{} failed a clean run:\n{}",
+        tool,
+        stderr
+    );
+
+    let output = run("use-after-free");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        !output.status.success() && stderr.contains(report),
+        "{} missed a use-after-free:\n{}",
+        tool,
+        stderr
+    );
+    println!("{} detected the use-after-free", tool);
+}